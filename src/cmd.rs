@@ -1,4 +1,7 @@
 use clap::{ArgMatches, App, AppSettings, Arg};
+use std::net::SocketAddr;
+use crate::message::{ReadConsistency, StalenessBound, SessionTag, KvRequest};
+use crate::state_machine::{Txn, Compare, TxnOp};
 
 #[derive(Debug)]
 pub struct Arguments {
@@ -19,6 +22,63 @@ pub fn get_cli_app() -> ArgMatches<'static> {
                     Arg::with_name("is_leader")
                         .help("boolean whether or not this server starts off as a Raft Leader")
                 )
+                .arg(
+                    Arg::with_name("address")
+                        .long("address")
+                        .takes_value(true)
+                        .help("Bind to this address instead of the default address pool; Format: 127.0.0.1:8006")
+                )
+                .arg(
+                    Arg::with_name("pre_vote")
+                        .long("pre-vote")
+                        .help("Run a PRE_VOTE round before starting an election, to avoid disruptive elections from partitioned nodes")
+                )
+                .arg(
+                    Arg::with_name("lease_read")
+                        .long("lease-read")
+                        .help("Serve leader reads from a lease based on the last quorum heartbeat, instead of a ReadIndex round")
+                )
+                .arg(
+                    Arg::with_name("clock_drift")
+                        .long("clock-drift")
+                        .takes_value(true)
+                        .help("Clock drift margin in milliseconds subtracted from the lease; defaults to 500")
+                )
+                .arg(
+                    Arg::with_name("in_memory_log")
+                        .long("in-memory-log")
+                        .help("Keep the log and hard state in memory only; they are lost when the server stops")
+                )
+                .arg(
+                    Arg::with_name("max_batch_size")
+                        .long("max-batch-size")
+                        .takes_value(true)
+                        .help("Most proposals written as one log append, and entries sent in one APPEND_ENTRY; defaults to 64")
+                )
+                .arg(
+                    Arg::with_name("max_batch_linger")
+                        .long("max-batch-linger")
+                        .takes_value(true)
+                        .help("Milliseconds a batch waits for more proposals before it is written; defaults to 2")
+                )
+                .arg(
+                    Arg::with_name("max_inflight_msgs")
+                        .long("max-inflight-msgs")
+                        .takes_value(true)
+                        .help("Most APPEND_ENTRY messages in flight to one peer; defaults to 8")
+                )
+                .arg(
+                    Arg::with_name("max_inflight_bytes")
+                        .long("max-inflight-bytes")
+                        .takes_value(true)
+                        .help("Most bytes of APPEND_ENTRY messages in flight to one peer; defaults to 1048576")
+                )
+                .arg(
+                    Arg::with_name("max_pending_proposals")
+                        .long("max-pending-proposals")
+                        .takes_value(true)
+                        .help("Most proposals queued for a batch before new ones are rejected; defaults to 1024")
+                )
         )
         .subcommand(
             App::new("client")
@@ -51,6 +111,582 @@ pub fn get_cli_app() -> ArgMatches<'static> {
                             .help("Remote Server Address; Format: 127.0.0.1:8001")
                     ),
                 )
+                .subcommand(App::new("add-node")
+                    .about("Adds a voting member to the cluster through the leader")
+                    .arg(
+                        Arg::with_name("address")
+                            .required(true)
+                            .takes_value(true)
+                            .help("Leader Server Address; Format: 127.0.0.1:8001")
+                    )
+                    .arg(
+                        Arg::with_name("node")
+                            .required(true)
+                            .takes_value(true)
+                            .help("Address of the Node to add; Format: 127.0.0.1:8006")
+                    ),
+                )
+                .subcommand(App::new("remove-node")
+                    .about("Removes a voting member from the cluster through the leader")
+                    .arg(
+                        Arg::with_name("address")
+                            .required(true)
+                            .takes_value(true)
+                            .help("Leader Server Address; Format: 127.0.0.1:8001")
+                    )
+                    .arg(
+                        Arg::with_name("node")
+                            .required(true)
+                            .takes_value(true)
+                            .help("Address of the Node to remove; Format: 127.0.0.1:8005")
+                    ),
+                )
+                .subcommand(App::new("add-learner")
+                    .about("Adds a non-voting learner to the cluster through the leader")
+                    .arg(
+                        Arg::with_name("address")
+                            .required(true)
+                            .takes_value(true)
+                            .help("Leader Server Address; Format: 127.0.0.1:8001")
+                    )
+                    .arg(
+                        Arg::with_name("node")
+                            .required(true)
+                            .takes_value(true)
+                            .help("Address of the Learner to add; Format: 127.0.0.1:8006")
+                    ),
+                )
+                .subcommand(App::new("promote")
+                    .about("Promotes a caught-up learner to a voting member through the leader")
+                    .arg(
+                        Arg::with_name("address")
+                            .required(true)
+                            .takes_value(true)
+                            .help("Leader Server Address; Format: 127.0.0.1:8001")
+                    )
+                    .arg(
+                        Arg::with_name("node")
+                            .required(true)
+                            .takes_value(true)
+                            .help("Address of the Learner to promote; Format: 127.0.0.1:8006")
+                    ),
+                )
+                .subcommand(App::new("set")
+                    .about("Sets a key to a value through the leader")
+                    .arg(
+                        Arg::with_name("address")
+                            .required(true)
+                            .takes_value(true)
+                            .help("Leader Server Address; Format: 127.0.0.1:8001")
+                    )
+                    .arg(
+                        Arg::with_name("key")
+                            .required(true)
+                            .takes_value(true)
+                            .help("Key to set")
+                    )
+                    .arg(
+                        Arg::with_name("value")
+                            .required(true)
+                            .takes_value(true)
+                            .help("Value to set")
+                    )
+                    .arg(
+                        Arg::with_name("ttl")
+                            .long("ttl")
+                            .takes_value(true)
+                            .help("Delete the key after this many milliseconds, unless it is set again")
+                    )
+                    .arg(
+                        Arg::with_name("lease")
+                            .long("lease")
+                            .takes_value(true)
+                            .conflicts_with("ttl")
+                            .help("Attach the key to a lease from lease-grant; it is deleted when the lease expires")
+                    )
+                    .arg(namespace_arg())
+                    .args(&session_args()),
+                )
+                .subcommand(App::new("get")
+                    .about("Gets the value of a key through the leader (linearizable, using ReadIndex)")
+                    .arg(
+                        Arg::with_name("address")
+                            .required(true)
+                            .takes_value(true)
+                            .help("Leader Server Address; Format: 127.0.0.1:8001")
+                    )
+                    .arg(
+                        Arg::with_name("key")
+                            .required(true)
+                            .takes_value(true)
+                            .help("Key to get")
+                    )
+
+                    .arg(namespace_arg())
+                    .args(&read_args()),
+                )
+                .subcommand(App::new("scan")
+                    .about("Lists keys from start up to (not including) end in order, with their values and versions")
+                    .arg(
+                        Arg::with_name("address")
+                            .required(true)
+                            .takes_value(true)
+                            .help("Leader Server Address; Format: 127.0.0.1:8001")
+                    )
+                    .arg(
+                        Arg::with_name("start")
+                            .required(true)
+                            .takes_value(true)
+                            .help("First key; to get the following page, pass the next key of the last reply")
+                    )
+                    .arg(
+                        Arg::with_name("end")
+                            .takes_value(true)
+                            .help("End key, not included; scans to the last key when unset")
+                    )
+                    .arg(
+                        Arg::with_name("limit")
+                            .long("limit")
+                            .takes_value(true)
+                            .help("Maximum number of keys in one page; defaults to 100")
+                    )
+                    .arg(namespace_arg())
+                    .args(&read_args()),
+                )
+                .subcommand(App::new("list")
+                    .about("Lists keys starting with a prefix in order, with their values and versions")
+                    .arg(
+                        Arg::with_name("address")
+                            .required(true)
+                            .takes_value(true)
+                            .help("Leader Server Address; Format: 127.0.0.1:8001")
+                    )
+                    .arg(
+                        Arg::with_name("prefix")
+                            .required(true)
+                            .takes_value(true)
+                            .help("Key prefix")
+                    )
+                    .arg(
+                        Arg::with_name("start")
+                            .long("start")
+                            .takes_value(true)
+                            .help("Key to start from; to get the following page, pass the next key of the last reply")
+                    )
+                    .arg(
+                        Arg::with_name("limit")
+                            .long("limit")
+                            .takes_value(true)
+                            .help("Maximum number of keys in one page; defaults to 100")
+                    )
+                    .arg(namespace_arg())
+                    .args(&read_args()),
+                )
+                .subcommand(App::new("delete")
+                    .about("Deletes a key through the leader")
+                    .arg(
+                        Arg::with_name("address")
+                            .required(true)
+                            .takes_value(true)
+                            .help("Leader Server Address; Format: 127.0.0.1:8001")
+                    )
+                    .arg(
+                        Arg::with_name("key")
+                            .required(true)
+                            .takes_value(true)
+                            .help("Key to delete")
+                    )
+                    .arg(namespace_arg())
+                    .args(&session_args()),
+                )
+                .subcommand(App::new("cas")
+                    .about("Sets a key to a new value through the leader, only if its current value matches")
+                    .arg(
+                        Arg::with_name("address")
+                            .required(true)
+                            .takes_value(true)
+                            .help("Leader Server Address; Format: 127.0.0.1:8001")
+                    )
+                    .arg(
+                        Arg::with_name("key")
+                            .required(true)
+                            .takes_value(true)
+                            .help("Key to set")
+                    )
+                    .arg(
+                        Arg::with_name("expected")
+                            .required(true)
+                            .takes_value(true)
+                            .help("Expected current value")
+                    )
+                    .arg(
+                        Arg::with_name("value")
+                            .required(true)
+                            .takes_value(true)
+                            .help("New value")
+                    )
+                    .arg(namespace_arg())
+                    .args(&session_args()),
+                )
+                .subcommand(App::new("set-if-absent")
+                    .about("Sets a key through the leader, only if it does not exist")
+                    .arg(
+                        Arg::with_name("address")
+                            .required(true)
+                            .takes_value(true)
+                            .help("Leader Server Address; Format: 127.0.0.1:8001")
+                    )
+                    .arg(
+                        Arg::with_name("key")
+                            .required(true)
+                            .takes_value(true)
+                            .help("Key to set")
+                    )
+                    .arg(
+                        Arg::with_name("value")
+                            .required(true)
+                            .takes_value(true)
+                            .help("Value to set")
+                    )
+                    .arg(namespace_arg())
+                    .args(&session_args()),
+                )
+                .subcommand(App::new("delete-if-version")
+                    .about("Deletes a key through the leader, only if its current version matches")
+                    .arg(
+                        Arg::with_name("address")
+                            .required(true)
+                            .takes_value(true)
+                            .help("Leader Server Address; Format: 127.0.0.1:8001")
+                    )
+                    .arg(
+                        Arg::with_name("key")
+                            .required(true)
+                            .takes_value(true)
+                            .help("Key to delete")
+                    )
+                    .arg(
+                        Arg::with_name("version")
+                            .required(true)
+                            .takes_value(true)
+                            .help("Expected current version, as returned by get")
+                    )
+                    .arg(namespace_arg())
+                    .args(&session_args()),
+                )
+                .subcommand(App::new("txn")
+                    .about("Atomically applies the --then ops if every --if condition holds, otherwise the --else ops")
+                    .arg(
+                        Arg::with_name("address")
+                            .required(true)
+                            .takes_value(true)
+                            .help("Leader Server Address; Format: 127.0.0.1:8001")
+                    )
+                    .arg(
+                        Arg::with_name("if")
+                            .long("if")
+                            .takes_value(true)
+                            .multiple(true)
+                            .number_of_values(1)
+                            .help("Condition; Format: value:<key>=<value>, version:<key>=<version> (version 0: key does not exist) or lock:<name>=<token>")
+                    )
+                    .arg(
+                        Arg::with_name("then")
+                            .long("then")
+                            .takes_value(true)
+                            .multiple(true)
+                            .number_of_values(1)
+                            .help("Op applied when all conditions hold; Format: set:<key>=<value> or delete:<key>")
+                    )
+                    .arg(
+                        Arg::with_name("else")
+                            .long("else")
+                            .takes_value(true)
+                            .multiple(true)
+                            .number_of_values(1)
+                            .help("Op applied when a condition fails; Format: set:<key>=<value> or delete:<key>")
+                    )
+                    .arg(namespace_arg())
+                    .args(&session_args()),
+                )
+                .subcommand(App::new("lease-grant")
+                    .about("Grants a key lease through the leader, and prints its id")
+                    .arg(
+                        Arg::with_name("address")
+                            .required(true)
+                            .takes_value(true)
+                            .help("Leader Server Address; Format: 127.0.0.1:8001")
+                    )
+                    .arg(
+                        Arg::with_name("ttl")
+                            .required(true)
+                            .takes_value(true)
+                            .help("Time to live in milliseconds, restarted by lease-keepalive")
+                    ),
+                )
+                .subcommand(App::new("lease-keepalive")
+                    .about("Restarts the time to live of a key lease")
+                    .arg(
+                        Arg::with_name("address")
+                            .required(true)
+                            .takes_value(true)
+                            .help("Leader Server Address; Format: 127.0.0.1:8001")
+                    )
+                    .arg(
+                        Arg::with_name("id")
+                            .required(true)
+                            .takes_value(true)
+                            .help("Lease id from lease-grant")
+                    ),
+                )
+                .subcommand(App::new("lease-revoke")
+                    .about("Revokes a key lease through the leader, deleting its keys")
+                    .arg(
+                        Arg::with_name("address")
+                            .required(true)
+                            .takes_value(true)
+                            .help("Leader Server Address; Format: 127.0.0.1:8001")
+                    )
+                    .arg(
+                        Arg::with_name("id")
+                            .required(true)
+                            .takes_value(true)
+                            .help("Lease id from lease-grant")
+                    ),
+                )
+                .subcommand(App::new("watch")
+                    .about("Prints every change to a key or prefix as it is applied, reconnecting to the next address if a server goes away")
+                    .arg(
+                        Arg::with_name("address")
+                            .required(true)
+                            .takes_value(true)
+                            .help("Server Address, any member can serve a watch; Format: 127.0.0.1:8001")
+                    )
+                    .arg(
+                        Arg::with_name("key")
+                            .required(true)
+                            .takes_value(true)
+                            .help("Key to watch")
+                    )
+                    .arg(
+                        Arg::with_name("prefix")
+                            .long("prefix")
+                            .help("Watch every key starting with key")
+                    )
+                    .arg(namespace_arg())
+                    .arg(
+                        Arg::with_name("from")
+                            .long("from")
+                            .takes_value(true)
+                            .help("Log index to resume from; by default only changes from now on are printed")
+                    )
+                    .arg(
+                        Arg::with_name("failover")
+                            .long("failover")
+                            .takes_value(true)
+                            .multiple(true)
+                            .number_of_values(1)
+                            .help("Other Server Address to reconnect to; Format: 127.0.0.1:8002")
+                    ),
+                )
+                .subcommand(App::new("compact")
+                    .about("Drops the history of every key older than a revision through the leader")
+                    .arg(
+                        Arg::with_name("address")
+                            .required(true)
+                            .takes_value(true)
+                            .help("Leader Server Address; Format: 127.0.0.1:8001")
+                    )
+                    .arg(
+                        Arg::with_name("revision")
+                            .required(true)
+                            .takes_value(true)
+                            .help("Oldest revision (log index) that can still be read afterwards")
+                    ),
+                )
+                .subcommand(App::new("lock")
+                    .about("Distributed locks with fencing tokens")
+                    .setting(AppSettings::SubcommandRequiredElseHelp)
+                    .subcommand(App::new("acquire")
+                        .about("Acquires a lock through the leader, and prints its fencing token")
+                        .arg(
+                            Arg::with_name("address")
+                                .required(true)
+                                .takes_value(true)
+                                .help("Leader Server Address; Format: 127.0.0.1:8001")
+                        )
+                        .arg(
+                            Arg::with_name("name")
+                                .required(true)
+                                .takes_value(true)
+                                .help("Lock name")
+                        )
+                        .arg(
+                            Arg::with_name("ttl")
+                                .long("ttl")
+                                .required(true)
+                                .takes_value(true)
+                                .help("Milliseconds until the lock is released, unless it is renewed")
+                        )
+                        .arg(
+                            Arg::with_name("wait")
+                                .long("wait")
+                                .help("Keep trying until the lock is free")
+                        ),
+                    )
+                    .subcommand(App::new("renew")
+                        .about("Restarts the ttl of a held lock")
+                        .arg(
+                            Arg::with_name("address")
+                                .required(true)
+                                .takes_value(true)
+                                .help("Leader Server Address; Format: 127.0.0.1:8001")
+                        )
+                        .arg(
+                            Arg::with_name("name")
+                                .required(true)
+                                .takes_value(true)
+                                .help("Lock name")
+                        )
+                        .arg(
+                            Arg::with_name("token")
+                                .required(true)
+                                .takes_value(true)
+                                .help("Fencing token from lock acquire")
+                        ),
+                    )
+                    .subcommand(App::new("release")
+                        .about("Releases a held lock")
+                        .arg(
+                            Arg::with_name("address")
+                                .required(true)
+                                .takes_value(true)
+                                .help("Leader Server Address; Format: 127.0.0.1:8001")
+                        )
+                        .arg(
+                            Arg::with_name("name")
+                                .required(true)
+                                .takes_value(true)
+                                .help("Lock name")
+                        )
+                        .arg(
+                            Arg::with_name("token")
+                                .required(true)
+                                .takes_value(true)
+                                .help("Fencing token from lock acquire")
+                        ),
+                    )
+                )
+                .subcommand(App::new("namespace")
+                    .about("Isolated keyspaces; key subcommands use one with --namespace")
+                    .setting(AppSettings::SubcommandRequiredElseHelp)
+                    .subcommand(App::new("create")
+                        .about("Creates an empty namespace through the leader")
+                        .arg(
+                            Arg::with_name("address")
+                                .required(true)
+                                .takes_value(true)
+                                .help("Leader Server Address; Format: 127.0.0.1:8001")
+                        )
+                        .arg(
+                            Arg::with_name("name")
+                                .required(true)
+                                .takes_value(true)
+                                .help("Namespace name: letters, digits, '-' and '_'")
+                        ),
+                    )
+                    .subcommand(App::new("drop")
+                        .about("Removes a namespace and every key in it through the leader")
+                        .arg(
+                            Arg::with_name("address")
+                                .required(true)
+                                .takes_value(true)
+                                .help("Leader Server Address; Format: 127.0.0.1:8001")
+                        )
+                        .arg(
+                            Arg::with_name("name")
+                                .required(true)
+                                .takes_value(true)
+                                .help("Namespace name")
+                        ),
+                    )
+                    .subcommand(App::new("list")
+                        .about("Lists every namespace")
+                        .arg(
+                            Arg::with_name("address")
+                                .required(true)
+                                .takes_value(true)
+                                .help("Leader Server Address; Format: 127.0.0.1:8001")
+                        )
+                        .args(&read_args()),
+                    )
+                    .subcommand(App::new("snapshot")
+                        .about("Writes a namespace's keys and their history to a file")
+                        .arg(
+                            Arg::with_name("address")
+                                .required(true)
+                                .takes_value(true)
+                                .help("Leader Server Address; Format: 127.0.0.1:8001")
+                        )
+                        .arg(
+                            Arg::with_name("name")
+                                .required(true)
+                                .takes_value(true)
+                                .help("Namespace name")
+                        )
+                        .arg(
+                            Arg::with_name("file")
+                                .required(true)
+                                .takes_value(true)
+                                .help("Snapshot file to write")
+                        ),
+                    )
+                    .subcommand(App::new("restore")
+                        .about("Replaces a namespace's keys with a snapshot file through the leader, creating the namespace if needed")
+                        .arg(
+                            Arg::with_name("address")
+                                .required(true)
+                                .takes_value(true)
+                                .help("Leader Server Address; Format: 127.0.0.1:8001")
+                        )
+                        .arg(
+                            Arg::with_name("name")
+                                .required(true)
+                                .takes_value(true)
+                                .help("Namespace name; can differ from the namespace the snapshot was taken from")
+                        )
+                        .arg(
+                            Arg::with_name("file")
+                                .required(true)
+                                .takes_value(true)
+                                .help("Snapshot file from namespace snapshot")
+                        ),
+                    )
+                )
+                .subcommand(App::new("register-session")
+                    .about("Registers a client session through the leader, and prints its client id")
+                    .arg(
+                        Arg::with_name("address")
+                            .required(true)
+                            .takes_value(true)
+                            .help("Leader Server Address; Format: 127.0.0.1:8001")
+                    ),
+                )
+                .subcommand(App::new("transfer-leader")
+                    .about("Gracefully moves leadership to another voting member")
+                    .arg(
+                        Arg::with_name("address")
+                            .required(true)
+                            .takes_value(true)
+                            .help("Leader Server Address; Format: 127.0.0.1:8001")
+                    )
+                    .arg(
+                        Arg::with_name("node")
+                            .required(true)
+                            .takes_value(true)
+                            .help("Node ID (listener address) of the new leader; Format: 127.0.0.1:8002")
+                    ),
+                )
         )
 
         .get_matches();
@@ -66,8 +702,172 @@ pub fn get_address(arg_matchers: &ArgMatches) -> Arguments {
     }
 }
 
+//get args for key/value requests
+pub fn get_kv_args(arg_matchers: &ArgMatches) -> Arguments {
+    Arguments{
+        key: arg_matchers.value_of("key").unwrap().to_string(),
+        value: arg_matchers.value_of("value").unwrap_or("").to_string(),
+        address: arg_matchers.value_of("address").unwrap().parse().unwrap(),
+    }
+}
+
+//get the read consistency for GET requests; stale reads default to a lag of 0 entries
+pub fn get_read_consistency(arg_matchers: &ArgMatches) -> Option<ReadConsistency> {
+    match arg_matchers.value_of("consistency") {
+        Some("linearizable") => Some(ReadConsistency::Linearizable),
+        Some("lease") => Some(ReadConsistency::Lease),
+        Some("stale") => {
+            let bound = match arg_matchers.value_of("max_lag_ms") {
+                Some(max_lag_ms) => StalenessBound::Millis(max_lag_ms.parse().unwrap()),
+                None => StalenessBound::Entries(arg_matchers.value_of("max_lag").unwrap_or("0").parse().unwrap()),
+            };
+            Some(ReadConsistency::Stale(bound))
+        }
+        _ => None,
+    }
+}
+
+//get the transaction from the --if, --then and --else arguments, keeping the order they were given in
+pub fn get_txn(arg_matchers: &ArgMatches) -> Txn {
+    let values = |name| arg_matchers.values_of(name).map(|values| values.collect::<Vec<_>>()).unwrap_or_default();
+    Txn {
+        compare: values("if").into_iter().map(parse_compare).collect(),
+        success: values("then").into_iter().map(parse_txn_op).collect(),
+        failure: values("else").into_iter().map(parse_txn_op).collect(),
+    }
+}
+
+fn parse_compare(arg: &str) -> Compare {
+    let (target, key, value) = match arg.split_once(':').and_then(|(target, rest)| {
+        rest.split_once('=').map(|(key, value)| (target, key.to_string(), value.to_string()))
+    }) {
+        Some(parsed) => parsed,
+        None => panic!("invalid condition {}, expected value:<key>=<value>, version:<key>=<version> or lock:<name>=<token>", arg),
+    };
+    match target {
+        "value" => Compare::Value { key, value },
+        "version" => Compare::Version { key, version: value.parse().unwrap() },
+        "lock" => Compare::LockToken { name: key, token: value.parse().unwrap() },
+        _ => panic!("invalid condition {}, expected value:<key>=<value>, version:<key>=<version> or lock:<name>=<token>", arg),
+    }
+}
+
+fn parse_txn_op(arg: &str) -> TxnOp {
+    match arg.split_once(':') {
+        Some(("set", assignment)) => match assignment.split_once('=') {
+            Some((key, value)) => TxnOp::Set { key: key.to_string(), value: value.to_string() },
+            None => panic!("invalid op {}, expected set:<key>=<value>", arg),
+        },
+        Some(("delete", key)) => TxnOp::Delete { key: key.to_string() },
+        _ => panic!("invalid op {}, expected set:<key>=<value> or delete:<key>", arg),
+    }
+}
+
+pub fn get_lock_token(arg_matchers: &ArgMatches) -> u64 {
+    arg_matchers.value_of("token").unwrap().parse().unwrap()
+}
+
+pub fn get_lease_id(arg_matchers: &ArgMatches) -> u64 {
+    arg_matchers.value_of("id").unwrap().parse().unwrap()
+}
+
+//--consistency, --max-lag, --max-lag-ms and --revision, shared by every read subcommand
+fn read_args<'a, 'b>() -> [Arg<'a, 'b>; 4] {
+    [
+        Arg::with_name("consistency")
+            .long("consistency")
+            .takes_value(true)
+            .possible_values(&["linearizable", "lease", "stale"])
+            .help("Read consistency; stale reads can be sent to any server"),
+        Arg::with_name("max_lag")
+            .long("max-lag")
+            .takes_value(true)
+            .help("stale reads: maximum number of entries behind the leader's commit index"),
+        Arg::with_name("max_lag_ms")
+            .long("max-lag-ms")
+            .takes_value(true)
+            .conflicts_with("max_lag")
+            .help("stale reads: maximum age in milliseconds of the leader commit index the server has caught up to"),
+        Arg::with_name("revision")
+            .long("revision")
+            .takes_value(true)
+            .help("Read as of a past revision (log index), like the revision of an earlier reply"),
+    ]
+}
+
+pub fn get_revision(arg_matchers: &ArgMatches) -> Option<u64> {
+    arg_matchers.value_of("revision").map(|revision| revision.parse().unwrap())
+}
+
+pub fn get_scan_limit(arg_matchers: &ArgMatches) -> Option<usize> {
+    arg_matchers.value_of("limit").map(|limit| limit.parse().unwrap())
+}
+
+//--client-id and --sequence, shared by every write subcommand
+fn session_args<'a, 'b>() -> [Arg<'a, 'b>; 2] {
+    [
+        Arg::with_name("client_id")
+            .long("client-id")
+            .takes_value(true)
+            .requires("sequence")
+            .help("Client session id from register-session, for exactly-once retries"),
+        Arg::with_name("sequence")
+            .long("sequence")
+            .takes_value(true)
+            .requires("client_id")
+            .help("Request sequence number within the client session; reuse it when retrying"),
+    ]
+}
+
+//get the optional client session tag for writes
+pub fn get_session(arg_matchers: &ArgMatches) -> Option<SessionTag> {
+    let client_id = arg_matchers.value_of("client_id")?;
+    Some(SessionTag {
+        client_id: client_id.parse().unwrap(),
+        sequence: arg_matchers.value_of("sequence").unwrap().parse().unwrap(),
+    })
+}
+
+//--namespace, shared by every subcommand on keys
+fn namespace_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("namespace")
+        .long("namespace")
+        .takes_value(true)
+        .help("Namespace of the keys; defaults to the default namespace")
+}
+
+pub fn get_namespace(arg_matchers: &ArgMatches) -> Option<String> {
+    arg_matchers.value_of("namespace").map(String::from)
+}
+
+//wrap a key request for the --namespace it names, if any
+pub fn in_namespace(arg_matchers: &ArgMatches, request: KvRequest) -> KvRequest {
+    match get_namespace(arg_matchers) {
+        Some(namespace) => KvRequest::Namespaced { namespace, request: Box::new(request) },
+        None => request,
+    }
+}
+
+//get the node address argument for membership changes
+pub fn get_node(arg_matchers: &ArgMatches) -> SocketAddr {
+    arg_matchers.value_of("node").unwrap().parse().unwrap()
+}
+
 pub fn print_address(arg_matchers: &ArgMatches) {
     let fmt_msg = format!("Remote-Address: {}", arg_matchers.value_of("address").unwrap());
     println!("{}", fmt_msg);
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_txn_args() {
+        assert_eq!(parse_compare("value:a=1=2"), Compare::Value { key: String::from("a"), value: String::from("1=2") });
+        assert_eq!(parse_compare("version:a=0"), Compare::Version { key: String::from("a"), version: 0 });
+        assert_eq!(parse_compare("lock:leader=12"), Compare::LockToken { name: String::from("leader"), token: 12 });
+        assert_eq!(parse_txn_op("set:a=x"), TxnOp::Set { key: String::from("a"), value: String::from("x") });
+        assert_eq!(parse_txn_op("delete:a"), TxnOp::Delete { key: String::from("a") });
+    }
+}
@@ -2,53 +2,127 @@ use std::net::{TcpStream, Shutdown, TcpListener,  SocketAddr};
 use crate::{kv_store};
 use crate::{message};
 use crate::{log};
-use std::io::{Read, Write, Error};
-use std::{thread, time};
+use std::io::{Read, Write, Error, BufRead, BufReader, Cursor};
+use std::time;
 use serde::{Serialize, Deserialize};
-use std::collections::VecDeque;
 use crate::message::MessageType::APPEND_ENTRY;
-use crate::message::{MessageType, AppendEntryRequestMessage, Message, init_message_queue, write_new_message_queue, RequestVoteMessage, RequestVoteReplyMessage, RequestVotePayload, RequestVoteReplyPayload, calculate_hash};
-use crate::kv_store::{kv_db_setup, set_key, get_key};
-use crate::leader::{IS_LEADER};
-use crate::log::{initialize_raft_log, get_raft_log};
+use crate::message::{MessageType, AppendEntryRequestMessage, Message, init_message_queue, push_message, RequestVoteMessage, RequestVoteReplyMessage, RequestVotePayload, RequestVoteReplyPayload, calculate_hash};
+use crate::leader::{is_leader, step_down, is_transferring_leadership, transfer_leadership};
+use crate::driver::{send_event, call_driver, RaftEvent, RaftRpc};
+use crate::server::get_node_addr;
+use crate::leader::{propose_command, propose_client_command};
+use crate::key_leases::{set_with_ttl, keep_alive_lease};
+use crate::locks::{renew_lock, release_lock};
+use crate::reads::{consistent_read, record_leader_commit};
+use crate::state_machine::{follow_leader_commit, read_key, read_range, read_prefix, read_namespaces,
+                           read_namespace_snapshot, CommandResponse, DEFAULT_SCAN_LIMIT};
+use crate::namespaces::{in_namespace, DEFAULT_NAMESPACE};
+use crate::message::{MessageHeader, KvRequest, KvRequestMessage, KvReply, WatchRequestMessage};
+use crate::watch::stream_watch_events;
+use crate::election::{ELECTION_TIMEOUT, accept_leader, grant_vote, grant_pre_vote, heard_from_leader_recently, record_leader_contact};
+use crate::storage::with_log_storage;
+use crate::log::{get_raft_log, LogCommand, LogEntry, append_replicated_entries, conflict_hint, last_log_index,
+                 get_hard_state, set_hard_state};
+use crate::membership::{restore_cluster_config, get_cluster_config, propose_config_change, is_learner};
+use crate::message::{ConfigChangeMessage, ConfigChangeReply, AppendEntryReplyMessage, AppendEntryReplyPayload,
+                     TransferLeaderMessage, TransferLeaderReply, HealthcheckResponse, HeartbeatMessage};
+
+//how long to wait for a peer's response before treating it as unreachable
+pub const RESPONSE_TIMEOUT: u64 = 1000;
 
 //main connection handler
 pub fn connection_handler(mut socket: TcpStream) {
-    let mut data_buffer = [0 as u8; 1024]; //buffer to read in messages
-
-    match socket.read(&mut data_buffer) {
-        Ok(size) => {
-            let msg = String::from_utf8(Vec::from(&data_buffer[0..size])).expect("Found Invalid UTF-8");
-
+    match read_request(&mut socket) {
+        Some((msg, pipelined)) => {
             println!("{:#?}", msg);
 
-            if msg.contains("HEALTHCHECK") {
-                let mut socket_clone = socket.try_clone().unwrap();
+            //dispatch on the msg_type field; client keys and values can contain any text
+            let header: MessageHeader = match serde_json::from_str(&msg) {
+                Ok(header) => header,
+                Err(e) => {
+                    println!("Unknown Message: {}", e);
+                    return
+                }
+            };
+            let socket_clone = socket.try_clone().unwrap();
 
-                healthcheck_handler(socket_clone);
-            }
-            if msg.contains("APPEND_ENTRY") {
-                let message: AppendEntryRequestMessage = serde_json::from_str(&msg).unwrap();
-                let mut socket_clone = socket.try_clone().unwrap();
-
-                let mut deque= get_message_queue();
-
-                append_entry_handler(socket_clone, deque, message);
-            }
-            if msg.contains("REQUEST_VOTE") {
-                let message: RequestVoteMessage = serde_json::from_str(&msg).unwrap();
-                let mut socket_clone = socket.try_clone().unwrap();
-
-                request_vote_handler(socket_clone, message);
+            match header.msg_type {
+                MessageType::HEALTHCHECK => {
+                    healthcheck_handler(socket_clone);
+                }
+                MessageType::APPEND_ENTRY => {
+                    let message: AppendEntryRequestMessage = serde_json::from_str(&msg).unwrap();
+                    append_entry_handler(socket_clone, message, pipelined);
+                }
+                MessageType::REQUEST_VOTE => {
+                    let message: RequestVoteMessage = serde_json::from_str(&msg).unwrap();
+                    rpc_handler(socket_clone, RaftRpc::RequestVote(message));
+                }
+                MessageType::HEARTBEAT => {
+                    let message: HeartbeatMessage = serde_json::from_str(&msg).unwrap();
+                    rpc_handler(socket_clone, RaftRpc::Heartbeat(message));
+                }
+                MessageType::PRE_VOTE => {
+                    let message: RequestVoteMessage = serde_json::from_str(&msg).unwrap();
+                    rpc_handler(socket_clone, RaftRpc::PreVote(message));
+                }
+                MessageType::TRANSFER_LEADER => {
+                    let message: TransferLeaderMessage = serde_json::from_str(&msg).unwrap();
+                    transfer_leader_handler(socket_clone, message);
+                }
+                MessageType::TIMEOUT_NOW => {
+                    timeout_now_handler(socket_clone);
+                }
+                MessageType::CONFIG_CHANGE => {
+                    let message: ConfigChangeMessage = serde_json::from_str(&msg).unwrap();
+                    config_change_handler(socket_clone, message);
+                }
+                MessageType::KV_REQUEST => {
+                    let message: KvRequestMessage = serde_json::from_str(&msg).unwrap();
+                    kv_request_handler(socket_clone, message);
+                }
+                MessageType::WATCH => {
+                    let message: WatchRequestMessage = serde_json::from_str(&msg).unwrap();
+                    stream_watch_events(socket_clone, message.payload);
+                }
             }
         }
-        Err(_) => {
+        None => {
             println!("Terminating Connection: {:#?}", socket.peer_addr().unwrap());
             socket.shutdown(Shutdown::Both).unwrap();
         }
     }
 }
 
+/*
+    clients keep the connection open for the reply, so a request is read until it is a complete JSON message;
+    requests like a namespace restore do not fit in a single read. A leader pipelining APPEND_ENTRY messages may
+    already have sent the next ones, so the bytes read past the first message are returned with it
+ */
+fn read_request(socket: &mut TcpStream) -> Option<(String, Vec<u8>)> {
+    let mut data_buffer = [0 as u8; 1024]; //buffer to read in messages
+    let mut request = Vec::new();
+    loop {
+        match socket.read(&mut data_buffer) {
+            Ok(0) if request.is_empty() => return None,
+            Ok(0) => break,
+            Ok(size) => request.extend_from_slice(&data_buffer[0..size]),
+            Err(_) => return None,
+        }
+        let mut messages = serde_json::Deserializer::from_slice(&request).into_iter::<serde_json::Value>();
+        match messages.next() {
+            Some(Err(e)) if e.is_eof() => continue,
+            Some(Ok(_)) => {
+                let end = messages.byte_offset();
+                let rest = request.split_off(end);
+                return Some((String::from_utf8(request).expect("Found Invalid UTF-8"), rest));
+            }
+            _ => break,
+        }
+    }
+    Some((String::from_utf8(request).expect("Found Invalid UTF-8"), Vec::new()))
+}
+
 //server handler for HEALTHCHECK requests
 fn healthcheck_handler(mut socket: TcpStream) {
     let resp_msg = message::get_healthcheck_resp_msg();
@@ -59,41 +133,372 @@ fn healthcheck_handler(mut socket: TcpStream) {
     socket.flush().unwrap();
 }
 
-//server handler for APPEND_ENTRY requests from leader
-//here we need to add the message to the individual server's message_queue, and send an arbitrary "ok" HEALTHCHECK response
-fn append_entry_handler(mut socket: TcpStream, mut deque: VecDeque<AppendEntryRequestMessage>,
-                        message: AppendEntryRequestMessage) {
+//server handler for APPEND_ENTRY requests
+//on the leader, the message is a client proposal: add it to the server's message_queue, and send an arbitrary "ok" HEALTHCHECK response
+//on followers and learners, the message is replication from the leader: append the entry to raft_log and reply with the match_index
+fn append_entry_handler(socket: TcpStream, message: AppendEntryRequestMessage, pipelined: Vec<u8>) {
+
+    if !is_leader() {
+        replicated_entry_handler(socket, message, pipelined);
+        return
+    }
+
+    //no new proposals are accepted while leadership is handed off
+    if is_transferring_leadership() {
+        rejection_handler(socket, MessageType::APPEND_ENTRY, "leadership transfer in progress");
+        return
+    }
 
     //send a generic "ok" response
     healthcheck_handler(socket);
 
     //add the message to the message_queue
-    deque.push_back(message);
-    write_new_message_queue(deque);
+    push_message(message);
+}
+
+/*
+    the leader pipelines APPEND_ENTRY messages over one connection, one JSON message per line; they are handled
+    in the order they were sent, and each reply goes back as one line, until the leader closes the connection
+ */
+fn replicated_entry_handler(mut socket: TcpStream, message: AppendEntryRequestMessage, pipelined: Vec<u8>) {
+    replicated_reply_handler(&mut socket, message);
+
+    socket.set_read_timeout(Some(time::Duration::from_millis(ELECTION_TIMEOUT))).unwrap();
+    let messages = BufReader::new(Cursor::new(pipelined).chain(socket.try_clone().unwrap())).lines();
+    for line in messages {
+        let message = match line.map(|line| serde_json::from_str::<AppendEntryRequestMessage>(&line)) {
+            Ok(Ok(message)) => message,
+            Ok(Err(_)) => continue,
+            Err(_) => return,
+        };
+        replicated_reply_handler(&mut socket, message);
+    }
+}
+
+//hand one replicated APPEND_ENTRY to the driver and write its reply back as one line
+fn replicated_reply_handler(socket: &mut TcpStream, message: AppendEntryRequestMessage) {
+    let mut serialized_reply = call_driver(RaftRpc::AppendEntry(message));
+    serialized_reply.push('\n');
+    if socket.write_all(serialized_reply.as_bytes()).is_ok() {
+        socket.flush().unwrap();
+    }
+}
+
+//server handler for Raft RPCs from peers; the driver handles the RPC and the reply goes back on this connection
+fn rpc_handler(mut socket: TcpStream, rpc: RaftRpc) {
+    let serialized_reply = call_driver(rpc);
+    socket.write_all(serialized_reply.as_bytes()).unwrap();
+    socket.flush().unwrap();
 }
 
-//server handler for REQUEST_VOTE
-fn request_vote_handler(mut socket: TcpStream, message: RequestVoteMessage) {
-    //follower handles proposed leader's request vote...
-    //for now just return a granted: true response to vote reqeust
-    let granted = RequestVoteReplyPayload {
-        term: message.payload.term,
-        granted: true,
+//handle a Raft RPC from a peer and return the serialized reply; runs on the driver thread once it is started
+pub fn handle_rpc(rpc: RaftRpc) -> String {
+    match rpc {
+        RaftRpc::AppendEntry(message) => append_replicated_message(message),
+        RaftRpc::Heartbeat(message) => heartbeat_message(message),
+        RaftRpc::RequestVote(message) => request_vote_message(message),
+        RaftRpc::PreVote(message) => pre_vote_message(message),
+    }
+}
+
+//the leader sends a batch of consecutive entries in entries; log_entry is the first of them
+fn append_replicated_message(message: AppendEntryRequestMessage) -> String {
+    let mut entries: Vec<LogEntry> = message.payload.entries.log_entries.into();
+    if entries.is_empty() {
+        entries.push(message.payload.log_entry);
+    }
+    let last_entry = entries.last().unwrap().clone();
+
+    //entries from a leader of an older term are rejected; a newer term is adopted
+    let mut hard_state = get_hard_state();
+    let previous_term = hard_state.current_term;
+    let success = if !accept_leader(message.payload.term, &mut hard_state) {
+        false
+    } else {
+        if hard_state.current_term > previous_term {
+            set_hard_state(hard_state.clone());
+            if is_leader() {
+                println!("Newer term {} seen, stepping down", hard_state.current_term);
+                step_down();
+            }
+        }
+        record_leader_contact();
+        with_log_storage(|storage| append_replicated_entries(storage, &entries))
+    };
+
+    let (match_index, conflict_term, conflict_index) = if success {
+
+        /*
+            membership changes take effect as soon as the config entry is appended; when a conflicting suffix was
+            truncated, the config entries it held are undone as well
+         */
+        restore_cluster_config();
+        (last_entry.prev_index + 1, None, None)
+    } else {
+        with_log_storage(|storage| {
+            let (conflict_term, conflict_index) = conflict_hint(storage, entries[0].prev_index);
+            (storage.last_index(), conflict_term, Some(conflict_index))
+        })
+    };
+
+    let node_addr = get_node_addr();
+    let reply_msg = AppendEntryReplyMessage {
+        src_id: calculate_hash(&node_addr),
+        src_addr: node_addr,
+        msg_type: MessageType::APPEND_ENTRY,
+        payload: AppendEntryReplyPayload {
+            term: hard_state.current_term,
+            success,
+            match_index,
+            conflict_term,
+            conflict_index,
+        },
+    };
+
+    serde_json::to_string(&reply_msg).unwrap()
+}
+
+//HEARTBEAT from the leader; acknowledges the leader's term and resets the election timeout
+fn heartbeat_message(message: HeartbeatMessage) -> String {
+    let mut hard_state = get_hard_state();
+    let success = message.payload.term >= hard_state.current_term;
+    if success {
+        if message.payload.term > hard_state.current_term {
+            hard_state.current_term = message.payload.term;
+            hard_state.voted_for = None;
+            set_hard_state(hard_state.clone());
+            if is_leader() {
+                println!("Newer term {} seen, stepping down", hard_state.current_term);
+                step_down();
+            }
+        }
+        record_leader_contact();
+        record_leader_commit(message.payload.commit_index);
+        follow_leader_commit(message.payload.commit_index, message.payload.commit_term);
+    }
+
+    let node_addr = get_node_addr();
+    let reply_msg = AppendEntryReplyMessage {
+        src_id: calculate_hash(&node_addr),
+        src_addr: node_addr,
+        msg_type: MessageType::HEARTBEAT,
+        payload: AppendEntryReplyPayload {
+            term: hard_state.current_term,
+            success,
+            match_index: last_log_index(&get_raft_log()),
+            conflict_term: None,
+            conflict_index: None,
+        },
     };
+
+    serde_json::to_string(&reply_msg).unwrap()
+}
+
+//REQUEST_VOTE from a candidate
+fn request_vote_message(message: RequestVoteMessage) -> String {
+    //follower handles proposed leader's request vote; learners never vote
+    //servers that heard from a leader within the election timeout ignore the request, which keeps leader leases safe
+    if heard_from_leader_recently() && !message.payload.leader_transfer {
+        return vote_reply(MessageType::REQUEST_VOTE, get_hard_state().current_term, false);
+    }
+    let mut hard_state = get_hard_state();
+    let previous_term = hard_state.current_term;
+    let granted = !is_learner() && grant_vote(&message.payload, &mut hard_state, &get_raft_log());
+    set_hard_state(hard_state.clone());
+
+    //a server that just voted gives the candidate a full election timeout to win
+    if granted {
+        send_event(RaftEvent::ResetElectionTimer);
+    }
+
+    //a leader that sees a newer term steps down to follower
+    if hard_state.current_term > previous_term && is_leader() {
+        println!("Newer term {} seen, stepping down", hard_state.current_term);
+        step_down();
+    }
+
+    vote_reply(MessageType::REQUEST_VOTE, hard_state.current_term, granted)
+}
+
+//PRE_VOTE from a server about to start an election; nothing is persisted, the reply only says whether a vote would be granted
+fn pre_vote_message(message: RequestVoteMessage) -> String {
+    let hard_state = get_hard_state();
+    let heard_from_leader = is_leader() || heard_from_leader_recently();
+    let granted = !is_learner() && grant_pre_vote(&message.payload, &hard_state, &get_raft_log(), heard_from_leader);
+
+    vote_reply(MessageType::PRE_VOTE, hard_state.current_term, granted)
+}
+
+fn vote_reply(msg_type: MessageType, term: i32, granted: bool) -> String {
+    let node_addr = get_node_addr();
     let reply_msg = RequestVoteReplyMessage {
-        src_id: calculate_hash(&socket.local_addr().unwrap()),
-        src_addr: socket.local_addr().unwrap(),
-        msg_type: MessageType::REQUEST_VOTE,
-        payload: granted
+        src_id: calculate_hash(&node_addr),
+        src_addr: node_addr,
+        msg_type,
+        payload: RequestVoteReplyPayload {
+            term,
+            granted,
+        },
     };
 
-    let serialized_reply = serde_json::to_string(&reply_msg).unwrap();
     //println!("Response Message: {:#?}", serialized_resp);
-    let reply_bytes = serialized_reply.as_bytes();
-    socket.write(&reply_bytes[0..reply_bytes.len()]).unwrap();
+    serde_json::to_string(&reply_msg).unwrap()
+}
+
+//server handler for CONFIG_CHANGE requests; only the leader can change cluster membership
+fn config_change_handler(mut socket: TcpStream, message: ConfigChangeMessage) {
+    let result = if !is_leader() {
+        Err(String::from("not the leader"))
+    } else if is_transferring_leadership() {
+        Err(String::from("leadership transfer in progress"))
+    } else {
+        propose_config_change(message.payload)
+    };
+
+    let reply_msg = match result {
+        Ok(config) => ConfigChangeReply {
+            msg_type: MessageType::CONFIG_CHANGE,
+            success: true,
+            reason: String::from("ok"),
+            config,
+        },
+        Err(reason) => ConfigChangeReply {
+            msg_type: MessageType::CONFIG_CHANGE,
+            success: false,
+            reason,
+            config: get_cluster_config(),
+        },
+    };
+
+    let serialized_reply = serde_json::to_string(&reply_msg).unwrap();
+    println!("Response Message: {:#?}", serialized_reply);
+    socket.write_all(serialized_reply.as_bytes()).unwrap();
+    socket.flush().unwrap();
+}
+
+//server handler for TRANSFER_LEADER requests from the client CLI
+fn transfer_leader_handler(mut socket: TcpStream, message: TransferLeaderMessage) {
+    let result = if is_leader() {
+        transfer_leadership(message.payload)
+    } else {
+        Err(String::from("not the leader"))
+    };
+
+    let reply_msg = match result {
+        Ok(_) => TransferLeaderReply {
+            msg_type: MessageType::TRANSFER_LEADER,
+            success: true,
+            reason: String::from("ok"),
+        },
+        Err(reason) => TransferLeaderReply {
+            msg_type: MessageType::TRANSFER_LEADER,
+            success: false,
+            reason,
+        },
+    };
+
+    let serialized_reply = serde_json::to_string(&reply_msg).unwrap();
+    println!("Response Message: {:#?}", serialized_reply);
+    socket.write_all(serialized_reply.as_bytes()).unwrap();
+    socket.flush().unwrap();
+}
+
+//server handler for TIMEOUT_NOW from the leader; acknowledge, then start an election right away
+fn timeout_now_handler(socket: TcpStream) {
+    healthcheck_handler(socket);
+
+    println!("TIMEOUT_NOW received; Server in CANDIDATE_STATE, Triggering Election");
+    send_event(RaftEvent::TimeoutNow);
+}
+
+//generic rejection response for requests the server cannot accept right now
+fn rejection_handler(mut socket: TcpStream, msg_type: MessageType, reason: &str) {
+    let resp_msg = HealthcheckResponse {
+        msg_type,
+        payload: String::from(reason),
+    };
+    let serialized_resp = serde_json::to_string(&resp_msg).unwrap();
+    println!("Response Message: {:#?}", serialized_resp);
+    socket.write_all(serialized_resp.as_bytes()).unwrap();
+    socket.flush().unwrap();
+}
+
+//server handler for client KV_REQUEST messages; writes go through the log, reads use ReadIndex
+fn kv_request_handler(mut socket: TcpStream, message: KvRequestMessage) {
+    let result = handle_kv_request(DEFAULT_NAMESPACE, message.payload);
+
+    let reply_msg = match result {
+        Ok(response) => KvReply {
+            msg_type: MessageType::KV_REQUEST,
+            success: response.success,
+            value: response.value,
+            reason: response.reason,
+            version: response.version,
+            page: response.page,
+            revision: response.revision,
+        },
+        Err(reason) => KvReply {
+            msg_type: MessageType::KV_REQUEST,
+            success: false,
+            value: None,
+            reason,
+            version: None,
+            page: None,
+            revision: None,
+        },
+    };
+
+    let serialized_reply = serde_json::to_string(&reply_msg).unwrap();
+    println!("Response Message: {:#?}", serialized_reply);
+    socket.write_all(serialized_reply.as_bytes()).unwrap();
     socket.flush().unwrap();
+}
 
+//a key request against one namespace; requests that do not name a namespace run against the default one
+fn handle_kv_request(namespace: &str, request: KvRequest) -> Result<CommandResponse, String> {
+    match request {
+        KvRequest::Namespaced { namespace, request } => handle_kv_request(&namespace, *request),
+        KvRequest::Get { key, consistency, revision } => consistent_read(consistency, || read_key(namespace, &key, revision)),
+        KvRequest::Scan { start, end, limit, consistency, revision } => consistent_read(consistency, || {
+            read_range(namespace, &start, end.as_deref(), limit.unwrap_or(DEFAULT_SCAN_LIMIT), revision)
+        }),
+        KvRequest::List { prefix, start, limit, consistency, revision } => consistent_read(consistency, || {
+            read_prefix(namespace, &prefix, start.as_deref(), limit.unwrap_or(DEFAULT_SCAN_LIMIT), revision)
+        }),
+        KvRequest::ListNamespaces { consistency } => consistent_read(consistency, read_namespaces),
+        KvRequest::SnapshotNamespace { namespace, consistency } => {
+            consistent_read(consistency, || read_namespace_snapshot(&namespace))
+        }
+        _ if !is_leader() => Err(String::from("not the leader")),
+        KvRequest::Set { key, value, session, ttl: Some(ttl), .. } => set_with_ttl(namespace, key, value, ttl, session),
+        KvRequest::Set { key, value, session, lease, .. } => {
+            propose_client_command(in_namespace(namespace, LogCommand::Set { key, value, lease }), session)
+        }
+        KvRequest::Delete { key, session } => propose_client_command(in_namespace(namespace, LogCommand::Delete { key }), session),
+        KvRequest::CompareAndSwap { key, expected, value, session } => {
+            propose_client_command(in_namespace(namespace, LogCommand::CompareAndSwap { key, expected, value }), session)
+        }
+        KvRequest::SetIfAbsent { key, value, session } => {
+            propose_client_command(in_namespace(namespace, LogCommand::SetIfAbsent { key, value }), session)
+        }
+        KvRequest::DeleteIfVersion { key, version, session } => {
+            propose_client_command(in_namespace(namespace, LogCommand::DeleteIfVersion { key, version }), session)
+        }
+        KvRequest::Txn { txn, session } => propose_client_command(in_namespace(namespace, LogCommand::Txn(txn)), session),
+        KvRequest::RegisterSession => propose_command(LogCommand::RegisterSession),
+        KvRequest::GrantLease { ttl } => propose_command(LogCommand::GrantLease { ttl }),
+        KvRequest::KeepAliveLease { id } => keep_alive_lease(id),
+        KvRequest::RevokeLease { id } => propose_command(LogCommand::RevokeLease { id }),
+        KvRequest::Compact { revision } => propose_command(LogCommand::Compact { revision }),
+        KvRequest::AcquireLock { name, ttl } => propose_command(LogCommand::AcquireLock { name, ttl }),
+        KvRequest::RenewLock { name, token } => renew_lock(&name, token),
+        KvRequest::ReleaseLock { name, token } => release_lock(&name, token),
+        KvRequest::CreateNamespace { namespace } => propose_command(LogCommand::CreateNamespace { namespace }),
+        KvRequest::DropNamespace { namespace } => propose_command(LogCommand::DropNamespace { namespace }),
+        KvRequest::RestoreNamespace { namespace, kv_data } => propose_command(LogCommand::RestoreNamespace { namespace, kv_data }),
+    }
 }
+
 //serialize generic message
 pub fn serialize_msg(message: message::Message) -> String {
     serde_json::to_string(&message).unwrap()
@@ -109,19 +514,27 @@ pub fn serialize_request_vote(request_vote_message: RequestVoteMessage) -> Strin
     serde_json::to_string(&request_vote_message).unwrap()
 }
 
-//returns the current deque message queue
-pub fn get_message_queue() -> VecDeque<AppendEntryRequestMessage>{
-    let deque_string = get_key(String::from("msg_queue"));
-    serde_json::from_str(&deque_string).unwrap()
+//read a single server response message; peers that accepted the connection but never reply time out
+pub fn read_resp(mut stream: TcpStream) -> Option<String> {
+    let mut resp = [0_u8; 1024]; // response buffer
+    stream.set_read_timeout(Some(time::Duration::from_millis(RESPONSE_TIMEOUT))).unwrap();
+    match stream.read(&mut resp) {
+        Ok(size) => Some(String::from_utf8(Vec::from(&resp[0..size])).unwrap()),
+        Err(e) => {
+            println!("Failed to receive data: {}", e);
+            None
+        }
+    }
 }
 
 //example function for client handling of server response
+//the server closes the connection after replying, so replies larger than one read (like scans) are read to the end
 pub fn handle_resp(mut stream: TcpStream) {
     //handle server response
-    let mut resp = [0 as u8; 1024]; // response buffer
-    match stream.read(&mut resp) {
-        Ok(size) => {
-            println!("{:#?}", String::from_utf8(Vec::from(&resp[0..size])).unwrap())
+    let mut resp = String::new();
+    match stream.read_to_string(&mut resp) {
+        Ok(_) => {
+            println!("{:#?}", resp)
         },
         Err(e) => {
             println!("Failed to receive data: {}", e);
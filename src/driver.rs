@@ -0,0 +1,248 @@
+//module for the Raft driver: one loop that owns the election, heartbeat and snapshot timers and handles peer RPCs
+
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+use crate::election::ELECTION_TIMEOUT;
+use crate::leader::{is_leader, leader_tick, HEARTBEAT_INTERVAL};
+use crate::membership::get_cluster_config;
+use crate::server::{get_node_addr, broadcast_request_vote, run_election};
+use crate::state_machine::{take_snapshot, SNAPSHOT_INTERVAL};
+use crate::connection_handler::handle_rpc;
+use crate::message::{AppendEntryRequestMessage, HeartbeatMessage, RequestVoteMessage};
+
+//where RPC handlers send events for the driver; None until the driver is started
+static EVENTS: Mutex<Option<Sender<RaftEvent>>> = Mutex::new(None);
+
+//what RPC handlers and leadership changes tell the driver
+#[derive(Debug)]
+pub enum RaftEvent {
+    //an APPEND_ENTRY or HEARTBEAT from the leader, or a granted vote: the election timer starts over
+    ResetElectionTimer,
+    //TIMEOUT_NOW from a leader transferring leadership: start an election right away
+    TimeoutNow,
+    //this server just became leader; its first heartbeat round goes out right away
+    BecameLeader,
+    //this server stepped down and waits a full election timeout before starting an election
+    SteppedDown,
+    //an RPC from a peer; the serialized reply goes back to the connection it came in on
+    Rpc(Box<RaftRpc>, Sender<String>),
+}
+
+//the Raft RPCs peers send; they change the term, the vote and the role, so only the driver thread handles them
+#[derive(Debug)]
+pub enum RaftRpc {
+    AppendEntry(AppendEntryRequestMessage),
+    Heartbeat(HeartbeatMessage),
+    RequestVote(RequestVoteMessage),
+    PreVote(RequestVoteMessage),
+}
+
+//what the driver does when a timer fires or an event arrives
+#[derive(Debug, Clone, PartialEq)]
+enum DriverAction {
+    StartElection,
+    TransferElection,
+    LeaderTick,
+    TakeSnapshot,
+}
+
+/*
+    deadlines of the driver's timers; only the election timer runs on followers, only the heartbeat timer on leaders.
+    The snapshot timer runs on every server
+ */
+struct Timers {
+    election_deadline: Instant,
+    next_heartbeat: Instant,
+    next_snapshot: Instant,
+}
+
+/*
+    a random timeout between one and two election timeouts, so followers that lost their leader at the same moment
+    do not keep starting elections together and splitting the vote
+ */
+fn randomized_election_timeout() -> Duration {
+    let random = RandomState::new().build_hasher().finish();
+    Duration::from_millis(ELECTION_TIMEOUT + random % ELECTION_TIMEOUT)
+}
+
+impl Timers {
+    fn new(now: Instant) -> Timers {
+        Timers {
+            election_deadline: now + randomized_election_timeout(),
+            next_heartbeat: now,
+            next_snapshot: now + Duration::from_millis(SNAPSHOT_INTERVAL),
+        }
+    }
+
+    fn next_deadline(&self, is_leader: bool) -> Instant {
+        let role_deadline = if is_leader { self.next_heartbeat } else { self.election_deadline };
+        role_deadline.min(self.next_snapshot)
+    }
+
+    fn on_event(&mut self, event: RaftEvent, now: Instant) -> Option<DriverAction> {
+        match event {
+            RaftEvent::ResetElectionTimer | RaftEvent::SteppedDown => {
+                self.election_deadline = now + randomized_election_timeout();
+                None
+            }
+            RaftEvent::TimeoutNow => {
+                self.election_deadline = now + randomized_election_timeout();
+                Some(DriverAction::TransferElection)
+            }
+            RaftEvent::BecameLeader => {
+                self.next_heartbeat = now;
+                None
+            }
+            //the driver loop handles RPCs itself; one from the leader resets the election timer with an event of its own
+            RaftEvent::Rpc(..) => None,
+        }
+    }
+
+    //a timer due at the same time as the snapshot timer fires on the next pass, right after the snapshot
+    fn on_timeout(&mut self, now: Instant, is_leader: bool) -> Option<DriverAction> {
+        if now >= self.next_snapshot {
+            self.next_snapshot = now + Duration::from_millis(SNAPSHOT_INTERVAL);
+            Some(DriverAction::TakeSnapshot)
+        } else if is_leader && now >= self.next_heartbeat {
+            self.next_heartbeat = now + Duration::from_millis(HEARTBEAT_INTERVAL);
+            Some(DriverAction::LeaderTick)
+        } else if !is_leader && now >= self.election_deadline {
+            self.election_deadline = now + randomized_election_timeout();
+            Some(DriverAction::StartElection)
+        } else {
+            None
+        }
+    }
+}
+
+pub fn send_event(event: RaftEvent) {
+    if let Some(sender) = EVENTS.lock().unwrap().as_ref() {
+        //the driver only goes away with the process
+        let _ = sender.send(event);
+    }
+}
+
+/*
+    hand an RPC from a peer to the driver and wait for its reply. Before the driver is started there is no timer
+    thread to race with, so the RPC is handled on the calling thread
+ */
+pub fn call_driver(rpc: RaftRpc) -> String {
+    let sender = EVENTS.lock().unwrap().clone();
+    match sender {
+        Some(sender) => {
+            let (reply, receiver) = mpsc::channel();
+            sender.send(RaftEvent::Rpc(Box::new(rpc), reply)).unwrap();
+            receiver.recv().unwrap()
+        }
+        None => handle_rpc(rpc),
+    }
+}
+
+//start the driver on a thread of its own, so the server keeps accepting connections while its timers run
+pub fn start_driver() {
+    let (sender, receiver) = mpsc::channel();
+    *EVENTS.lock().unwrap() = Some(sender);
+    thread::spawn(move || run_driver(receiver));
+}
+
+/*
+    wait for the next event until the nearest timer deadline, then run whatever the event or timer calls for.
+    Peer RPCs, elections and leader ticks all run on the driver thread, so term and role changes never race
+ */
+fn run_driver(events: Receiver<RaftEvent>) {
+    let mut timers = Timers::new(Instant::now());
+    loop {
+        let deadline = timers.next_deadline(is_leader());
+        let action = match events.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+            Ok(RaftEvent::Rpc(rpc, reply)) => {
+                //the peer may have hung up while the RPC waited its turn
+                let _ = reply.send(handle_rpc(*rpc));
+                None
+            }
+            Ok(event) => timers.on_event(event, Instant::now()),
+            Err(RecvTimeoutError::Timeout) => timers.on_timeout(Instant::now(), is_leader()),
+            Err(RecvTimeoutError::Disconnected) => return,
+        };
+        match action {
+            Some(DriverAction::StartElection) => start_election(),
+            Some(DriverAction::TransferElection) => run_election(false, true),
+            Some(DriverAction::LeaderTick) => leader_tick(),
+            Some(DriverAction::TakeSnapshot) => take_snapshot(),
+            None => {}
+        }
+    }
+}
+
+fn start_election() {
+    //learners (and nodes that are not yet members) never start elections
+    if !get_cluster_config().is_voter(&get_node_addr()) {
+        println!("ELECTION_TIMER expired; Server is not a voting member, waiting for the leader");
+        return
+    }
+    println!("ELECTION_TIMER expired; Server in CANDIDATE_STATE, Triggering Election");
+    broadcast_request_vote();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_election_timer() {
+        let start = Instant::now();
+        let mut timers = Timers::new(start);
+        let timeout = Duration::from_millis(ELECTION_TIMEOUT);
+        assert!(timers.next_deadline(false) >= start + timeout && timers.next_deadline(false) < start + timeout * 2);
+        assert_eq!(timers.on_timeout(start + timeout / 2, false), None);
+
+        //hearing from the leader pushes the election back
+        let contact = start + timeout;
+        assert_eq!(timers.on_event(RaftEvent::ResetElectionTimer, contact), None);
+        assert_eq!(timers.on_timeout(contact + timeout / 2, false), None);
+        assert_eq!(timers.on_timeout(contact + timeout * 2, false), Some(DriverAction::StartElection));
+        assert!(timers.next_deadline(false) >= contact + timeout * 3);
+
+        assert_eq!(timers.on_event(RaftEvent::TimeoutNow, start), Some(DriverAction::TransferElection));
+    }
+
+    #[test]
+    fn test_randomized_election_timeout() {
+        let timeouts: Vec<Duration> = (0..20).map(|_| randomized_election_timeout()).collect();
+        let timeout = Duration::from_millis(ELECTION_TIMEOUT);
+        assert!(timeouts.iter().all(|t| *t >= timeout && *t < timeout * 2));
+        assert!(timeouts.iter().any(|t| *t != timeouts[0]));
+    }
+
+    #[test]
+    fn test_heartbeat_timer() {
+        let start = Instant::now();
+        let mut timers = Timers::new(start);
+        let interval = Duration::from_millis(HEARTBEAT_INTERVAL);
+
+        timers.on_event(RaftEvent::BecameLeader, start);
+        assert_eq!(timers.on_timeout(start, true), Some(DriverAction::LeaderTick));
+        assert_eq!(timers.next_deadline(true), start + interval);
+        assert_eq!(timers.on_timeout(start + interval / 2, true), None);
+        //leaders never start elections, however long they go without a leader's contact
+        assert_eq!(timers.on_timeout(start + Duration::from_millis(ELECTION_TIMEOUT * 2), true),
+                   Some(DriverAction::LeaderTick));
+    }
+
+    #[test]
+    fn test_snapshot_timer() {
+        let start = Instant::now();
+        let mut timers = Timers::new(start);
+        let interval = Duration::from_millis(SNAPSHOT_INTERVAL);
+        timers.on_event(RaftEvent::ResetElectionTimer, start + interval);
+
+        //the snapshot goes first, and the heartbeat due at the same time follows on the next pass
+        assert_eq!(timers.on_timeout(start + interval, true), Some(DriverAction::TakeSnapshot));
+        assert_eq!(timers.on_timeout(start + interval, true), Some(DriverAction::LeaderTick));
+        assert_eq!(timers.on_timeout(start + interval, false), None);
+        assert_eq!(timers.next_snapshot, start + interval * 2);
+    }
+}
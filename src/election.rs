@@ -0,0 +1,181 @@
+//module for Raft election rules: RequestVote/PreVote granting and leader contact tracking
+
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time;
+use crate::log::{TheLog, HardState, last_log_index, last_log_term};
+use crate::message::RequestVotePayload;
+use crate::driver::{send_event, RaftEvent};
+
+//how long a follower waits without hearing from a leader before it starts an election
+pub const ELECTION_TIMEOUT: u64 = 10000;
+
+//whether candidates run a PRE_VOTE round before incrementing their term
+static PRE_VOTE: AtomicBool = AtomicBool::new(false);
+
+//last time this server received replication from a leader
+static LAST_LEADER_CONTACT: Mutex<Option<time::Instant>> = Mutex::new(None);
+
+pub fn set_pre_vote(enabled: bool) {
+    PRE_VOTE.store(enabled, Ordering::SeqCst);
+}
+
+pub fn is_pre_vote_enabled() -> bool {
+    PRE_VOTE.load(Ordering::SeqCst)
+}
+
+pub fn record_leader_contact() {
+    *LAST_LEADER_CONTACT.lock().unwrap() = Some(time::Instant::now());
+    send_event(RaftEvent::ResetElectionTimer);
+}
+
+//whether a leader has been heard from within the election timeout
+pub fn heard_from_leader_recently() -> bool {
+    match *LAST_LEADER_CONTACT.lock().unwrap() {
+        Some(last_contact) => last_contact.elapsed() < time::Duration::from_millis(ELECTION_TIMEOUT),
+        None => false,
+    }
+}
+
+/*
+    election restriction: a vote is only granted to a candidate whose log is at least as up-to-date,
+    comparing the term of the last entries first and then the log length
+ */
+pub fn is_log_up_to_date(candidate: &RequestVotePayload, raft_log: &TheLog) -> bool {
+    let last_term = last_log_term(raft_log);
+    if candidate.last_log_term != last_term {
+        return candidate.last_log_term > last_term;
+    }
+    candidate.last_log_index >= last_log_index(raft_log)
+}
+
+/*
+    PRE_VOTE: would this server grant a vote for the proposed term? Nothing is persisted, and a server that
+    has recently heard from a leader refuses, so a rejoining partitioned node cannot disrupt a healthy leader
+ */
+pub fn grant_pre_vote(candidate: &RequestVotePayload, hard_state: &HardState, raft_log: &TheLog,
+                      heard_from_leader: bool) -> bool {
+    !heard_from_leader && candidate.term > hard_state.current_term && is_log_up_to_date(candidate, raft_log)
+}
+
+//REQUEST_VOTE: updates hard_state for a newer term, and records the vote when granted
+pub fn grant_vote(candidate: &RequestVotePayload, hard_state: &mut HardState, raft_log: &TheLog) -> bool {
+    if candidate.term < hard_state.current_term {
+        return false;
+    }
+    if candidate.term > hard_state.current_term {
+        hard_state.current_term = candidate.term;
+        hard_state.voted_for = None;
+    }
+    let can_vote = hard_state.voted_for.is_none() || hard_state.voted_for == candidate.candidate;
+    if can_vote && is_log_up_to_date(candidate, raft_log) {
+        hard_state.voted_for = candidate.candidate;
+        return true;
+    }
+    false
+}
+
+/*
+    APPEND_ENTRY: a server follows a leader of its current term or a newer one, and adopts a newer term. The
+    leader's term is the one it sends with the batch, never the terms of the entries, which keep the term they
+    were created in and can be older than the follower's
+ */
+pub fn accept_leader(leader_term: i32, hard_state: &mut HardState) -> bool {
+    if leader_term < hard_state.current_term {
+        return false;
+    }
+    if leader_term > hard_state.current_term {
+        hard_state.current_term = leader_term;
+        hard_state.voted_for = None;
+    }
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::VecDeque;
+    use std::net::SocketAddr;
+    use crate::log::{LogEntry, append_replicated_entries};
+    use crate::storage::{LogStorage, MemoryLogStorage};
+
+    fn raft_log_with_terms(terms: &[i32]) -> TheLog {
+        let mut log_entries = VecDeque::new();
+        for (index, term) in terms.iter().enumerate() {
+            log_entries.push_back(LogEntry{
+                leader_term: *term,
+                leader_id: String::from("test"),
+                prev_index: index as i32,
+                prev_term: 0,
+                leader_commit_index: index as i32 + 1,
+                command: None,
+            });
+        }
+        TheLog{log_entries}
+    }
+
+    fn candidate(port: u16, term: i32, last_log_index: i32, last_log_term: i32) -> RequestVotePayload {
+        RequestVotePayload {
+            last_log_index,
+            last_log_term,
+            term,
+            candidate: Some(SocketAddr::from(([127, 0, 0, 1], port))),
+            leader_transfer: false,
+        }
+    }
+
+    #[test]
+    fn test_log_up_to_date() {
+        let raft_log = raft_log_with_terms(&[1, 1, 2]);
+        assert!(is_log_up_to_date(&candidate(8002, 3, 3, 2), &raft_log));
+        assert!(is_log_up_to_date(&candidate(8002, 3, 1, 3), &raft_log));
+        assert!(!is_log_up_to_date(&candidate(8002, 3, 2, 2), &raft_log));
+        assert!(!is_log_up_to_date(&candidate(8002, 3, 5, 1), &raft_log));
+    }
+
+    #[test]
+    fn test_pre_vote_does_not_change_term() {
+        let raft_log = raft_log_with_terms(&[1]);
+        let hard_state = HardState{current_term: 1, voted_for: None};
+
+        assert!(grant_pre_vote(&candidate(8002, 2, 1, 1), &hard_state, &raft_log, false));
+        assert!(!grant_pre_vote(&candidate(8002, 2, 1, 1), &hard_state, &raft_log, true));
+        assert!(!grant_pre_vote(&candidate(8002, 1, 1, 1), &hard_state, &raft_log, false));
+        assert_eq!(hard_state.current_term, 1);
+    }
+
+    #[test]
+    fn test_one_vote_per_term() {
+        let raft_log = raft_log_with_terms(&[1]);
+        let mut hard_state = HardState{current_term: 1, voted_for: None};
+
+        assert!(grant_vote(&candidate(8002, 2, 1, 1), &mut hard_state, &raft_log));
+        assert_eq!(hard_state.current_term, 2);
+        assert!(grant_vote(&candidate(8002, 2, 1, 1), &mut hard_state, &raft_log));
+        assert!(!grant_vote(&candidate(8003, 2, 1, 1), &mut hard_state, &raft_log));
+
+        //a newer term resets the vote
+        assert!(grant_vote(&candidate(8003, 3, 1, 1), &mut hard_state, &raft_log));
+        assert!(!grant_vote(&candidate(8004, 2, 1, 1), &mut hard_state, &raft_log));
+    }
+
+    #[test]
+    fn test_accept_leader_with_older_entries() {
+        //the follower moved on to term 3 while its log is still empty; the term 3 leader sends it term 1 entries
+        let mut hard_state = HardState{current_term: 3, voted_for: None};
+        let mut storage = MemoryLogStorage::default();
+        let raft_log = raft_log_with_terms(&[1, 1]);
+        let entries: Vec<LogEntry> = raft_log.log_entries.into();
+
+        assert!(!accept_leader(entries[1].leader_term, &mut hard_state));
+        assert!(accept_leader(3, &mut hard_state));
+        assert!(append_replicated_entries(&mut storage, &entries));
+        assert_eq!(storage.last_index(), 2);
+        assert_eq!(hard_state.current_term, 3);
+
+        //a newer leader's term is adopted, and the vote of the old term is forgotten
+        hard_state.voted_for = Some(SocketAddr::from(([127, 0, 0, 1], 8002)));
+        assert!(accept_leader(4, &mut hard_state));
+        assert_eq!(hard_state, HardState{current_term: 4, voted_for: None});
+    }
+}
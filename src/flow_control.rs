@@ -0,0 +1,106 @@
+//module for flow control: how much the leader keeps in flight to each peer, and how many proposals it queues
+
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+//default for the most APPEND_ENTRY messages in flight to one peer
+pub const DEFAULT_MAX_INFLIGHT_MSGS: usize = 8;
+
+//default for the most bytes of APPEND_ENTRY messages in flight to one peer
+pub const DEFAULT_MAX_INFLIGHT_BYTES: usize = 1024 * 1024;
+
+//default for the most proposals waiting for a batch before new ones are turned away
+pub const DEFAULT_MAX_PENDING_PROPOSALS: usize = 1024;
+
+static MAX_INFLIGHT_MSGS: AtomicUsize = AtomicUsize::new(DEFAULT_MAX_INFLIGHT_MSGS);
+
+static MAX_INFLIGHT_BYTES: AtomicUsize = AtomicUsize::new(DEFAULT_MAX_INFLIGHT_BYTES);
+
+static MAX_PENDING_PROPOSALS: AtomicUsize = AtomicUsize::new(DEFAULT_MAX_PENDING_PROPOSALS);
+
+pub fn set_flow_control(max_inflight_msgs: usize, max_inflight_bytes: usize, max_pending_proposals: usize) {
+    MAX_INFLIGHT_MSGS.store(max_inflight_msgs.max(1), Ordering::SeqCst);
+    MAX_INFLIGHT_BYTES.store(max_inflight_bytes, Ordering::SeqCst);
+    MAX_PENDING_PROPOSALS.store(max_pending_proposals.max(1), Ordering::SeqCst);
+}
+
+pub fn get_max_pending_proposals() -> usize {
+    MAX_PENDING_PROPOSALS.load(Ordering::SeqCst)
+}
+
+/*
+    the APPEND_ENTRY messages sent to one peer and not acknowledged yet, oldest first, with the first log index
+    and size of each. A peer in probe mode gets one message at a time
+ */
+#[derive(Debug, Clone, Default)]
+pub struct Inflights {
+    messages: VecDeque<(i32, usize)>,
+    bytes: usize,
+    probing: bool,
+}
+
+impl Inflights {
+    pub fn new(probing: bool) -> Inflights {
+        Inflights { probing, ..Default::default() }
+    }
+
+    /*
+        whether a message of the given size can be sent now. One message is always allowed when nothing is in
+        flight, so a single batch larger than the byte limit still goes out
+     */
+    pub fn has_room(&self, bytes: usize) -> bool {
+        if self.messages.is_empty() {
+            return true;
+        }
+        let max_msgs = if self.probing { 1 } else { MAX_INFLIGHT_MSGS.load(Ordering::SeqCst) };
+        self.messages.len() < max_msgs && self.bytes + bytes <= MAX_INFLIGHT_BYTES.load(Ordering::SeqCst)
+    }
+
+    pub fn add(&mut self, first_index: i32, bytes: usize) {
+        self.messages.push_back((first_index, bytes));
+        self.bytes += bytes;
+    }
+
+    //the oldest message was acknowledged or given up on; returns its first log index
+    pub fn free_first(&mut self) -> Option<i32> {
+        let (first_index, bytes) = self.messages.pop_front()?;
+        self.bytes -= bytes;
+        Some(first_index)
+    }
+
+    //the peer's log is known now, so the window opens up to the full limits
+    pub fn stop_probing(&mut self) {
+        self.probing = false;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_inflights_limits() {
+        let mut inflights = Inflights::new(false);
+        assert!(inflights.has_room(DEFAULT_MAX_INFLIGHT_BYTES * 2));
+        for index in 0..DEFAULT_MAX_INFLIGHT_MSGS {
+            inflights.add(index as i32 + 1, 10);
+        }
+        assert!(!inflights.has_room(10));
+
+        assert_eq!(inflights.free_first(), Some(1));
+        assert!(inflights.has_room(10));
+        assert!(!inflights.has_room(DEFAULT_MAX_INFLIGHT_BYTES));
+    }
+
+    #[test]
+    fn test_inflights_probe() {
+        let mut inflights = Inflights::new(true);
+        inflights.add(5, 10);
+        assert!(!inflights.has_room(10));
+
+        inflights.stop_probing();
+        assert!(inflights.has_room(10));
+        assert_eq!(inflights.free_first(), Some(5));
+        assert_eq!(inflights.free_first(), None);
+    }
+}
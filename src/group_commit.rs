@@ -0,0 +1,118 @@
+//module for group commit: proposals made at the same time are appended, written and replicated as one batch
+
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::sync::Mutex;
+use std::{thread, time};
+use crate::log::LogCommand;
+use crate::leader::commit_batch;
+use crate::flow_control::get_max_pending_proposals;
+use crate::state_machine::CommandResponse;
+
+//default for the most commands appended, and entries sent to a peer, in one batch
+pub const DEFAULT_MAX_BATCH_SIZE: usize = 64;
+
+//default for how long (in ms) a batch waits for more proposals before it is written
+pub const DEFAULT_MAX_BATCH_LINGER: u64 = 2;
+
+static MAX_BATCH_SIZE: AtomicUsize = AtomicUsize::new(DEFAULT_MAX_BATCH_SIZE);
+
+static MAX_BATCH_LINGER: AtomicU64 = AtomicU64::new(DEFAULT_MAX_BATCH_LINGER);
+
+//proposals waiting for the next batch, in arrival order
+static PENDING_PROPOSALS: Mutex<Vec<PendingProposal>> = Mutex::new(Vec::new());
+
+//held by the proposer currently writing a batch; everyone else's proposals queue up behind it
+static BATCH_LOCK: Mutex<()> = Mutex::new(());
+
+struct PendingProposal {
+    command: LogCommand,
+    result: mpsc::Sender<Result<CommandResponse, String>>,
+}
+
+pub fn set_batching(max_batch_size: usize, max_batch_linger: u64) {
+    MAX_BATCH_SIZE.store(max_batch_size.max(1), Ordering::SeqCst);
+    MAX_BATCH_LINGER.store(max_batch_linger, Ordering::SeqCst);
+}
+
+pub fn get_max_batch_size() -> usize {
+    MAX_BATCH_SIZE.load(Ordering::SeqCst)
+}
+
+/*
+    queue a command for the next batch and wait for its result. Whichever waiting proposer gets the batch lock
+    writes the next batch for everyone: it lingers until the batch is full or the linger time is up, so commands
+    proposed while the previous batch was being replicated share one synced log write and one APPEND_ENTRY round
+ */
+pub fn propose_batched(command: LogCommand) -> Result<CommandResponse, String> {
+    let (sender, receiver) = mpsc::channel();
+    {
+        //backpressure: turn the proposal away rather than queue without bound behind slow replication
+        let mut pending = PENDING_PROPOSALS.lock().unwrap();
+        if pending.len() >= get_max_pending_proposals() {
+            return Err(format!("Too many pending proposals ({}); retry later", pending.len()));
+        }
+        pending.push(PendingProposal { command, result: sender });
+    }
+    loop {
+        let _batch_guard = BATCH_LOCK.lock().unwrap();
+        if let Ok(result) = receiver.try_recv() {
+            return result;
+        }
+        linger();
+        write_next_batch();
+    }
+}
+
+fn linger() {
+    let linger = time::Duration::from_millis(MAX_BATCH_LINGER.load(Ordering::SeqCst));
+    let started = time::Instant::now();
+    while PENDING_PROPOSALS.lock().unwrap().len() < get_max_batch_size() && started.elapsed() < linger {
+        thread::sleep(time::Duration::from_micros(200));
+    }
+}
+
+fn write_next_batch() {
+    let batch = take_batch(&mut PENDING_PROPOSALS.lock().unwrap(), get_max_batch_size());
+    if batch.is_empty() {
+        return;
+    }
+    println!("Committing a batch of {} proposals", batch.len());
+    let (commands, senders): (Vec<LogCommand>, Vec<_>) = batch.into_iter()
+        .map(|proposal| (proposal.command, proposal.result))
+        .unzip();
+    for (sender, result) in senders.into_iter().zip(commit_batch(commands)) {
+        //the proposer may have given up waiting
+        let _ = sender.send(result);
+    }
+}
+
+//the oldest proposals, at most max_batch_size of them
+fn take_batch(pending: &mut Vec<PendingProposal>, max_batch_size: usize) -> Vec<PendingProposal> {
+    let batch_size = pending.len().min(max_batch_size);
+    pending.drain(..batch_size).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn proposal(key: &str) -> PendingProposal {
+        let (sender, _) = mpsc::channel();
+        PendingProposal { command: LogCommand::Delete { key: String::from(key) }, result: sender }
+    }
+
+    #[test]
+    fn test_take_batch() {
+        let mut pending = vec![proposal("a"), proposal("b"), proposal("c")];
+
+        let batch = take_batch(&mut pending, 2);
+        let keys: Vec<String> = batch.into_iter().map(|proposal| match proposal.command {
+            LogCommand::Delete { key } => key,
+            _ => unreachable!(),
+        }).collect();
+        assert_eq!(keys, vec!["a", "b"]);
+        assert_eq!(pending.len(), 1);
+        assert!(take_batch(&mut pending, 2).len() == 1 && pending.is_empty());
+    }
+}
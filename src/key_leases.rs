@@ -0,0 +1,126 @@
+//module for key leases: the leader tracks ttls and proposes the expiry of key leases through the log
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::{thread, time};
+use crate::log::LogCommand;
+use crate::leader::{propose_command, propose_client_command};
+use crate::message::SessionTag;
+use crate::state_machine::{get_key_leases, cached_session_response, CommandResponse, KeyLease};
+use crate::namespaces::in_namespace;
+
+//when each key lease expires, only tracked by the leader
+static LEASE_DEADLINES: Mutex<Option<HashMap<u64, time::Instant>>> = Mutex::new(None);
+
+//set while a worker proposes revocations, so the next tick does not propose the same ones again
+static REVOKING_LEASES: AtomicBool = AtomicBool::new(false);
+
+//a new leader does not know how long the old leader waited, so every lease gets its full ttl again
+pub fn reset_lease_deadlines() {
+    *LEASE_DEADLINES.lock().unwrap() = Some(HashMap::new());
+}
+
+//grant a key lease through the log, and return its id
+pub fn grant_lease(ttl: u64) -> Result<u64, String> {
+    let response = propose_command(LogCommand::GrantLease { ttl })?;
+    match response.value {
+        Some(id) if response.success => Ok(id.parse().unwrap()),
+        _ => Err(response.reason),
+    }
+}
+
+//keepalive only restarts the leader's timer for the lease, nothing is appended to the log
+pub fn keep_alive_lease(id: u64) -> Result<CommandResponse, String> {
+    let key_lease = match get_key_leases().remove(&id) {
+        Some(key_lease) => key_lease,
+        None => return Err(format!("unknown lease {}", id)),
+    };
+    let mut deadlines = LEASE_DEADLINES.lock().unwrap();
+    deadlines.get_or_insert_with(HashMap::new)
+        .insert(id, time::Instant::now() + time::Duration::from_millis(key_lease.ttl));
+    Ok(CommandResponse::ok(Some(key_lease.ttl.to_string())))
+}
+
+//set a key on a new lease of its own, which expires after ttl milliseconds
+pub fn set_with_ttl(namespace: &str, key: String, value: String, ttl: u64, session: Option<SessionTag>)
+                    -> Result<CommandResponse, String> {
+    //a retried request is answered from the session, without granting another lease
+    if let Some(tag) = &session {
+        if let Some(response) = cached_session_response(tag.client_id, tag.sequence) {
+            return Ok(response);
+        }
+    }
+    let lease = grant_lease(ttl)?;
+    propose_client_command(in_namespace(namespace, LogCommand::Set { key, value, lease: Some(lease) }), session)
+}
+
+/*
+    run by the leader workload: propose a RevokeLease for every key lease past its deadline. A proposal waits for
+    its entry to commit, so the proposals run on a worker thread and leave the driver free for heartbeats and RPCs
+ */
+pub fn expire_leases() {
+    if REVOKING_LEASES.swap(true, Ordering::SeqCst) {
+        return
+    }
+    thread::spawn(|| {
+        revoke_expired_leases();
+        REVOKING_LEASES.store(false, Ordering::SeqCst);
+    });
+}
+
+fn revoke_expired_leases() {
+    let expired = {
+        let mut deadlines = LEASE_DEADLINES.lock().unwrap();
+        expired_leases(&get_key_leases(), deadlines.get_or_insert_with(HashMap::new), time::Instant::now())
+    };
+    for id in expired {
+        println!("Key lease {} expired, proposing its revocation", id);
+        if let Err(e) = propose_command(LogCommand::RevokeLease { id }) {
+            println!("Failed to revoke expired lease {}: {}", id, e);
+        }
+    }
+}
+
+//leases without a deadline yet start their ttl now; deadlines of revoked leases are dropped
+fn expired_leases(leases: &HashMap<u64, KeyLease>, deadlines: &mut HashMap<u64, time::Instant>,
+                  now: time::Instant) -> Vec<u64> {
+    deadlines.retain(|id, _| leases.contains_key(id));
+    let mut expired = Vec::new();
+    for (id, key_lease) in leases {
+        let deadline = deadlines.entry(*id).or_insert_with(|| now + time::Duration::from_millis(key_lease.ttl));
+        if *deadline <= now {
+            expired.push(*id);
+        }
+    }
+    expired.sort_unstable();
+    expired
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key_lease(ttl: u64) -> KeyLease {
+        KeyLease { ttl, keys: vec![(String::from("default"), String::from("k"))] }
+    }
+
+    #[test]
+    fn test_expired_leases() {
+        let mut leases = HashMap::new();
+        leases.insert(3, key_lease(1000));
+        leases.insert(5, key_lease(5000));
+        let mut deadlines = HashMap::new();
+        let start = time::Instant::now();
+
+        //untracked leases get a full ttl
+        assert!(expired_leases(&leases, &mut deadlines, start).is_empty());
+        assert_eq!(expired_leases(&leases, &mut deadlines, start + time::Duration::from_millis(1000)), vec![3]);
+
+        //revoked leases are no longer tracked
+        leases.remove(&3);
+        assert!(expired_leases(&leases, &mut deadlines, start + time::Duration::from_millis(2000)).is_empty());
+        assert!(!deadlines.contains_key(&3));
+        assert_eq!(expired_leases(&leases, &mut deadlines, start + time::Duration::from_millis(5000)), vec![5]);
+    }
+}
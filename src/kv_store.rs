@@ -1,28 +1,118 @@
 use pickledb::{PickleDb, PickleDbDumpPolicy, SerializationMethod};
+use serde::Serialize;
+use serde::de::DeserializeOwned;
 use std::error::Error;
+use std::fs::File;
 use std::path::Path;
+use std::sync::Mutex;
 
-pub fn kv_db_setup() -> Result<PickleDb, Box<dyn Error>> {
-    match check_db_exists() {
-        Ok(true) => {
-            let db = PickleDb::load("kv.db", PickleDbDumpPolicy::DumpUponRequest, SerializationMethod::Json).unwrap();
-            Ok(db)
-        }
-        Ok(false) => {
-            let db = PickleDb::new("kv.db", PickleDbDumpPolicy::AutoDump, SerializationMethod::Json);
-            Ok(db)
+//state machine data: the client keyspaces and the state replicated with them (leases, locks, sessions)
+const KV_DB_PATH: &str = "kv.db";
+
+//Raft metadata, kept in a file of its own so nothing written through the state machine can overwrite it
+const META_DB_PATH: &str = "raft_meta.db";
+
+/*
+    the one open handle on the metadata file. A handle loaded for each call would write its whole (possibly stale)
+    copy back when dropped, undoing another thread's write, so every metadata read and write goes through this one
+ */
+static META_DB: Mutex<Option<PickleDb>> = Mutex::new(None);
+
+//the one open handle on the state machine file, shared for the same reason as META_DB
+static KV_DB: Mutex<Option<PickleDb>> = Mutex::new(None);
+
+//the Raft metadata a server stores; only these keys exist in the metadata file
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MetaKey {
+    HardState,
+    MsgQueue,
+    Peers,
+    ClusterConfig,
+    BootstrapConfig,
+    NodeAddr,
+    ReplicationProgress,
+    SnapshotMetadata,
+}
+
+impl MetaKey {
+    fn name(self) -> &'static str {
+        match self {
+            MetaKey::HardState => "hard_state",
+            MetaKey::MsgQueue => "msg_queue",
+            MetaKey::Peers => "peers",
+            MetaKey::ClusterConfig => "cluster_config",
+            MetaKey::BootstrapConfig => "bootstrap_config",
+            MetaKey::NodeAddr => "node_addr",
+            MetaKey::ReplicationProgress => "replication_progress",
+            MetaKey::SnapshotMetadata => "snapshot_metadata",
         }
-        _ => {panic!()}
     }
 }
 
-fn check_db_exists() -> Result<bool, Box<dyn Error>> {
-    Ok(Path::new("kv.db").exists())
+//every change to a db file is written out as soon as it is made
+fn db_setup(path: &str) -> PickleDb {
+    if check_db_exists(path).unwrap() {
+        PickleDb::load(path, PickleDbDumpPolicy::AutoDump, SerializationMethod::Json).unwrap()
+    } else {
+        PickleDb::new(path, PickleDbDumpPolicy::AutoDump, SerializationMethod::Json)
+    }
+}
+
+//run f on the metadata file while holding its lock; f must not call back into the metadata functions
+fn with_meta_db<T, F>(f: F) -> T where F: FnOnce(&mut PickleDb) -> T {
+    let mut db = META_DB.lock().unwrap();
+    f(db.get_or_insert_with(|| db_setup(META_DB_PATH)))
+}
+
+//run f on the state machine file while holding its lock
+fn with_kv_db<T, F>(f: F) -> T where F: FnOnce(&mut PickleDb) -> T {
+    let mut db = KV_DB.lock().unwrap();
+    f(db.get_or_insert_with(|| db_setup(KV_DB_PATH)))
+}
+
+fn check_db_exists(path: &str) -> Result<bool, Box<dyn Error>> {
+    Ok(Path::new(path).exists())
+}
+
+//getter for Raft metadata; None until it is first set
+pub fn get_meta<T: DeserializeOwned>(key: MetaKey) -> Option<T> {
+    with_meta_db(|db| db.get::<T>(key.name()))
+}
+
+pub fn set_meta<T: Serialize>(key: MetaKey, value: &T) {
+    with_meta_db(|db| {
+        db.set(key.name(), value).unwrap();
+        sync_to_disk(META_DB_PATH).unwrap();
+    })
+}
+
+/*
+    read-modify-write of one metadata value (its default when unset), with no other metadata access in between;
+    f must not call back into the metadata functions
+ */
+pub fn update_meta<T, R, F>(key: MetaKey, f: F) -> R
+    where T: Serialize + DeserializeOwned + Default, F: FnOnce(&mut T) -> R {
+    with_meta_db(|db| {
+        let mut value = db.get::<T>(key.name()).unwrap_or_default();
+        let result = f(&mut value);
+        db.set(key.name(), &value).unwrap();
+        sync_to_disk(META_DB_PATH).unwrap();
+        result
+    })
+}
+
+/*
+    pickledb writes a change to a temporary file and renames it over the old one, but never syncs either; sync the
+    file and its directory so the change survives a crash, not just a restart
+ */
+pub fn sync_to_disk(path: &str) -> std::io::Result<()> {
+    File::open(path)?.sync_all()?;
+    let dir = Path::new(path).parent().filter(|dir| !dir.as_os_str().is_empty()).unwrap_or(Path::new("."));
+    File::open(dir)?.sync_all()
 }
 
 pub fn get_key(key: String) -> String {
-    let db = kv_db_setup().unwrap();
-    let value = db.get::<String>(&*key);
+    let value = with_kv_db(|db| db.get::<String>(&*key));
     if value == None {
         String::from("Bad-Key!")
     } else {
@@ -31,11 +121,9 @@ pub fn get_key(key: String) -> String {
 }
 
 pub fn set_key(key: String, value: String) {
-    let mut db = kv_db_setup().unwrap();
-    db.set(&*key, &value).unwrap()
+    with_kv_db(|db| db.set(&*key, &value).unwrap())
 }
 
 pub fn delete_key(key: String) -> pickledb::error::Result<bool> {
-    let mut db = kv_db_setup().unwrap();
-    db.rem(&*key)
+    with_kv_db(|db| db.rem(&*key))
 }
\ No newline at end of file
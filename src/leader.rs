@@ -4,18 +4,94 @@ use std::net::{TcpStream, SocketAddr};
 use crate::message;
 use crate::log;
 use crate::connection_handler;
-use std::io::Write;
+use std::io::{Read, Write, BufRead, BufReader};
 use serde::{Serialize, Deserialize};
 use crate::message::{RequestVoteMessage, calculate_hash, MessageType, Message, AppendEntryRequestMessage, RequestVotePayload, write_new_message_queue, process_next_message};
 use crate::connection_handler::{serialize_msg, handle_resp, serialize_append_entry,
-                                serialize_request_vote, get_message_queue};
+                                serialize_request_vote};
 use std::collections::VecDeque;
-use crate::log::{LogEntry, TheLog, validate_log_entry};
-use crate::kv_store::get_key;
+use crate::log::{LogEntry, TheLog, validate_log_entry, get_raft_log, last_log_index, entry_at};
+use crate::connection_handler::{read_resp, RESPONSE_TIMEOUT};
+use crate::flow_control::Inflights;
+use crate::membership::{ConfigChange, retry_pending_config_change, get_cluster_config};
+use crate::message::{AppendEntryRequest, ConfigChangeMessage, AppendEntryReplyMessage, AppendEntryReplyPayload, TransferLeaderMessage};
+use crate::server::get_node_addr;
+use crate::driver::{send_event, RaftEvent};
+use crate::kv_store::{get_meta, set_meta, update_meta, MetaKey};
+use std::collections::HashMap;
 use std::{thread, time};
+use std::sync::atomic::{AtomicBool, AtomicI32, Ordering};
+use std::sync::{Arc, Mutex};
+use crate::log::{get_hard_state, initialize_hard_state};
+use crate::message::{HeartbeatMessage, HeartbeatPayload};
+use crate::election::ELECTION_TIMEOUT;
+use crate::reads::{get_clock_drift_margin, lease_duration};
+use crate::log::{append_commands, LogCommand, next_index_after_conflict};
+use crate::storage::with_log_storage;
+use crate::group_commit::{propose_batched, get_max_batch_size};
+use crate::message::{KvRequest, KvRequestMessage, KvReply};
+use crate::state_machine::{get_commit_index, set_commit_index, get_last_applied, apply_committed_entries,
+                           take_applied_response, cached_session_response, CommandResponse};
+use crate::message::SessionTag;
+use crate::key_leases::{reset_lease_deadlines, expire_leases};
 
 pub static IS_LEADER: state::Container = state::Container::new();
 
+//set while the leader hands leadership off to another node; new proposals are rejected
+static TRANSFERRING_LEADERSHIP: AtomicBool = AtomicBool::new(false);
+
+//how long the leader waits for a transfer target to catch up before giving up
+const LEADER_TRANSFER_TIMEOUT: u64 = 10000;
+
+//interval between leader heartbeats; must be well below the election timeout
+pub const HEARTBEAT_INTERVAL: u64 = 2000;
+
+//until when the leader may serve lease reads without a heartbeat round
+static LEASE_EXPIRY: Mutex<Option<time::Instant>> = Mutex::new(None);
+
+//log index of the NoOp the leader appended when it took office; reads wait until it commits
+static TERM_START_INDEX: AtomicI32 = AtomicI32::new(i32::MAX);
+
+//last successful response from each peer, used by CheckQuorum
+static LAST_PEER_ACK: Mutex<Option<HashMap<SocketAddr, time::Instant>>> = Mutex::new(None);
+
+//held while replicating to a peer, so proposers and the driver never sync the same peer at once
+static PEER_SYNC_LOCKS: Mutex<Option<HashMap<SocketAddr, Arc<Mutex<()>>>>> = Mutex::new(None);
+
+//IS_LEADER holds an AtomicBool so that the role can change after startup (elections, step down)
+pub fn is_leader() -> bool {
+    IS_LEADER.get::<AtomicBool>().load(Ordering::SeqCst)
+}
+
+pub fn set_leader(is_leader: bool) {
+    IS_LEADER.get::<AtomicBool>().store(is_leader, Ordering::SeqCst);
+}
+
+//leaders that see a newer term, or lose contact with a quorum, step down to follower
+pub fn step_down() {
+    set_leader(false);
+    *LEASE_EXPIRY.lock().unwrap() = None;
+    send_event(RaftEvent::SteppedDown);
+}
+
+//adopt a newer term seen in a reply, stepping down if this server was leader; returns whether the term was newer
+fn observe_term(term: i32) -> bool {
+    let hard_state = get_hard_state();
+    if term <= hard_state.current_term {
+        return false;
+    }
+    initialize_hard_state(term);
+    if is_leader() {
+        println!("Newer term {} seen, stepping down", term);
+        step_down();
+    }
+    true
+}
+
+pub fn is_transferring_leadership() -> bool {
+    TRANSFERRING_LEADERSHIP.load(Ordering::SeqCst)
+}
+
 
 //generic healthcheck for testing
 //use this for the HEARTBEAT
@@ -63,20 +139,569 @@ pub fn append_entry_request(dest_addr: String, append_entry_req: message::Append
     }
 }
 
-pub fn do_leader_workload() {
-    thread::spawn(move|| {
-        println!("Separate Thread for Leader Stuff");
-        while *IS_LEADER.get::<bool>() {
-            println!("doing leader stuff");
+//replication progress the leader tracks for each follower/learner
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct PeerProgress {
+    pub next_index: i32,
+    pub match_index: i32,
+    #[serde(default)]
+    pub state: ReplicationState,
+}
 
+/*
+    a peer is probed until the leader knows where its log matches: one APPEND_ENTRY at a time, so a peer that is
+    far behind or unreachable never has a window of messages queued for it. It replicates with a full window
+    after an APPEND_ENTRY succeeds, and goes back to probing on a rejection or a failed send
+ */
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+pub enum ReplicationState {
+    #[default]
+    Probe,
+    Replicate,
+}
 
-            process_next_message();
-            let sleep_time = time::Duration::from_millis(10000);
-            time::Instant::now();
-            thread::sleep(sleep_time);
-        }
+//getter for a peer's replication progress; unknown peers start at the leader's last entry
+pub fn get_peer_progress(peer: &SocketAddr) -> PeerProgress {
+    let progress: HashMap<String, PeerProgress> = get_replication_progress();
+    match progress.get(&peer.to_string()) {
+        Some(peer_progress) => peer_progress.clone(),
+        None => PeerProgress {
+            next_index: last_log_index(&get_raft_log()).max(1),
+            match_index: 0,
+            state: ReplicationState::Probe,
+        },
+    }
+}
+
+fn set_peer_progress(peer: &SocketAddr, peer_progress: PeerProgress) {
+    update_meta(MetaKey::ReplicationProgress, |progress: &mut HashMap<String, PeerProgress>| {
+        progress.insert(peer.to_string(), peer_progress)
     });
 }
 
+//a new leader forgets any progress it tracked during an earlier term
+fn reset_replication_progress() {
+    set_meta(MetaKey::ReplicationProgress, &HashMap::<String, PeerProgress>::new());
+}
+
+fn get_replication_progress() -> HashMap<String, PeerProgress> {
+    get_meta(MetaKey::ReplicationProgress).unwrap_or_default()
+}
+
+//how a pipeline of APPEND_ENTRY messages to a peer ended; after a rejected batch the next sync starts further back
+enum PipelineEnd {
+    CaughtUp,
+    Rejected,
+    Failed,
+}
+
+//the APPEND_ENTRY batch starting at index: up to max batch size consecutive entries, first one as log_entry
+fn append_batch(raft_log: &TheLog, index: i32, term: i32) -> Option<AppendEntryRequest> {
+    let entry = entry_at(raft_log, index)?;
+    let log_entries: VecDeque<LogEntry> = (index..index + get_max_batch_size() as i32)
+        .map_while(|index| entry_at(raft_log, index))
+        .collect();
+    Some(AppendEntryRequest {
+        term,
+        log_entry: entry,
+        entries: TheLog{log_entries},
+    })
+}
+
+/*
+    bring a peer's log up to the leader's last index. APPEND_ENTRY batches are pipelined: as many as the
+    peer's in-flight limits allow are sent before waiting for a reply, and next_index moves past each batch as
+    soon as it is sent. A rejected batch means the peer's log is behind or conflicting; the batches sent after it
+    are rejected too, so next_index rolls back using the peer's conflict hint and the next sync (at the latest on
+    the next leader tick) starts from there. Only one sync to a peer runs at a time, so its progress is never
+    updated from two threads
+ */
+pub fn sync_peer(peer: &SocketAddr) -> PeerProgress {
+    let peer_lock = peer_sync_lock(peer);
+    let _sync_guard = peer_lock.lock().unwrap();
+    let raft_log = get_raft_log();
+    let mut peer_progress = get_peer_progress(peer);
+
+    if entry_at(&raft_log, peer_progress.next_index).is_some() {
+        let end = pipeline_entries(peer, &raft_log, &mut peer_progress);
+        set_peer_progress(peer, peer_progress.clone());
+        if let PipelineEnd::Rejected = end {
+            println!("{} rejected APPEND_ENTRY, retrying from index {}", peer, peer_progress.next_index);
+        }
+    }
+    peer_progress
+}
+
+fn peer_sync_lock(peer: &SocketAddr) -> Arc<Mutex<()>> {
+    let mut locks = PEER_SYNC_LOCKS.lock().unwrap();
+    locks.get_or_insert_with(HashMap::new).entry(*peer).or_default().clone()
+}
+
+fn pipeline_entries(peer: &SocketAddr, raft_log: &TheLog, peer_progress: &mut PeerProgress) -> PipelineEnd {
+    let mut stream = match TcpStream::connect(peer) {
+        Ok(stream) => stream,
+        Err(e) => {
+            println!("Failed to Connect to Server: {:#?}", e);
+            return PipelineEnd::Failed;
+        }
+    };
+    stream.set_read_timeout(Some(time::Duration::from_millis(RESPONSE_TIMEOUT))).unwrap();
+    let mut replies = BufReader::new(stream.try_clone().unwrap()).lines();
+
+    let term = get_hard_state().current_term;
+    let mut in_flight = Inflights::new(peer_progress.state == ReplicationState::Probe);
+    loop {
+        while let Some(append_entry_req) = append_batch(raft_log, peer_progress.next_index, term) {
+            let batch_len = append_entry_req.entries.log_entries.len() as i32;
+            let serialized = serialize_append_entry_line(&stream, append_entry_req);
+            if !in_flight.has_room(serialized.len()) || stream.write_all(serialized.as_bytes()).is_err() {
+                break;
+            }
+            in_flight.add(peer_progress.next_index, serialized.len());
+            peer_progress.next_index += batch_len;
+        }
+
+        let sent_from = match in_flight.free_first() {
+            Some(sent_from) => sent_from,
+            None => return PipelineEnd::CaughtUp,
+        };
+        let reply = replies.next()
+            .and_then(|line| line.ok())
+            .and_then(|line| serde_json::from_str::<AppendEntryReplyMessage>(&line).ok());
+        match reply.map(|reply| reply.payload) {
+            Some(reply) if reply.success => {
+                peer_progress.match_index = peer_progress.match_index.max(reply.match_index);
+                peer_progress.state = ReplicationState::Replicate;
+                in_flight.stop_probing();
+            }
+            Some(reply) if observe_term(reply.term) => {
+                peer_progress.next_index = sent_from;
+                return PipelineEnd::Failed;
+            }
+            Some(reply) => {
+                peer_progress.next_index = with_log_storage(|storage| {
+                    next_index_after_conflict(storage, sent_from, reply.conflict_term, reply.conflict_index)
+                });
+                peer_progress.state = ReplicationState::Probe;
+                return PipelineEnd::Rejected;
+            }
+            //batches that were never acknowledged are sent again next time
+            None => {
+                peer_progress.next_index = sent_from;
+                peer_progress.state = ReplicationState::Probe;
+                return PipelineEnd::Failed;
+            }
+        }
+    }
+}
+
+//an APPEND_ENTRY message for a pipeline, ready to write; the peer reads one message per line
+fn serialize_append_entry_line(stream: &TcpStream, append_entry_req: AppendEntryRequest) -> String {
+    let src_addr = stream.local_addr().unwrap();
+    let msg = AppendEntryRequestMessage {
+        src_addr,
+        src_id: calculate_hash(&src_addr),
+        msg_type: MessageType::APPEND_ENTRY,
+        payload: append_entry_req,
+    };
+    let mut serialized = serialize_append_entry(msg);
+    serialized.push('\n');
+    serialized
+}
+
+//replicate the log up to and including an entry to each of the given peers, returning the peers that acknowledged it
+pub fn replicate_entry(peers: Vec<SocketAddr>, entry: LogEntry) -> Vec<SocketAddr> {
+    let entry_index = entry.prev_index + 1;
+    let mut acks = Vec::new();
+    for peer in peers {
+        if sync_peer(&peer).match_index >= entry_index {
+            acks.push(peer);
+        }
+    }
+    acks
+}
+
+//CONFIG_CHANGE Request, sent by the client CLI to the leader
+pub fn config_change_request(dest_addr: String, change: ConfigChange) {
+    match TcpStream::connect(dest_addr) {
+        Ok(mut stream) => {
+            let msg = ConfigChangeMessage {
+                src_addr: stream.local_addr().unwrap(),
+                src_id: calculate_hash(&stream.local_addr().unwrap()),
+                msg_type: MessageType::CONFIG_CHANGE,
+                payload: change,
+            };
+
+            let serialized_bytes = serde_json::to_string(&msg).unwrap();
+            stream.write_all(serialized_bytes.as_bytes()).unwrap();
+
+            handle_resp(stream);
+        }
+        Err(e) => {
+            println!("Failed to Connect to Server: {:#?}", e)
+        }
+    }
+}
+
+//TRANSFER_LEADER Request, sent by the client CLI to the leader
+pub fn transfer_leader_request(dest_addr: String, target: SocketAddr) {
+    match TcpStream::connect(dest_addr) {
+        Ok(mut stream) => {
+            let msg = TransferLeaderMessage {
+                src_addr: stream.local_addr().unwrap(),
+                src_id: calculate_hash(&stream.local_addr().unwrap()),
+                msg_type: MessageType::TRANSFER_LEADER,
+                payload: target,
+            };
+
+            let serialized_bytes = serde_json::to_string(&msg).unwrap();
+            stream.write_all(serialized_bytes.as_bytes()).unwrap();
+
+            handle_resp(stream);
+        }
+        Err(e) => {
+            println!("Failed to Connect to Server: {:#?}", e)
+        }
+    }
+}
+
+/*
+    leadership transfer:
+    1) stop accepting new proposals
+    2) bring the target's log fully up to date
+    3) send TIMEOUT_NOW so the target starts an election immediately, then step down
+ */
+pub fn transfer_leadership(target: SocketAddr) -> Result<(), String> {
+    if target == get_node_addr() {
+        return Err(format!("{} is already the leader", target));
+    }
+    if !get_cluster_config().is_voter(&target) {
+        return Err(format!("{} is not a voting member", target));
+    }
+
+    TRANSFERRING_LEADERSHIP.store(true, Ordering::SeqCst);
+    let result = hand_off_leadership(&target);
+    TRANSFERRING_LEADERSHIP.store(false, Ordering::SeqCst);
+    result
+}
+
+fn hand_off_leadership(target: &SocketAddr) -> Result<(), String> {
+    let deadline = time::Instant::now() + time::Duration::from_millis(LEADER_TRANSFER_TIMEOUT);
+    while sync_peer(target).match_index < last_log_index(&get_raft_log()) {
+        if time::Instant::now() >= deadline {
+            return Err(format!("timed out waiting for {} to catch up", target));
+        }
+        thread::sleep(time::Duration::from_millis(100));
+    }
+
+    if !send_timeout_now(target) {
+        return Err(format!("{} did not acknowledge TIMEOUT_NOW", target));
+    }
+
+    //step down to follower; the target's election will establish the new leader
+    println!("Leadership transferred to {}, stepping down", target);
+    step_down();
+    Ok(())
+}
+
+//TIMEOUT_NOW Request, tells the target to start an election without waiting for its election timer
+fn send_timeout_now(target: &SocketAddr) -> bool {
+    match TcpStream::connect(target) {
+        Ok(mut stream) => {
+            let msg = Message {
+                src_addr: stream.local_addr().unwrap(),
+                src_id: calculate_hash(&stream.local_addr().unwrap()),
+                msg_type: MessageType::TIMEOUT_NOW,
+                payload: get_node_addr().to_string(),
+            };
+
+            let serialized_bytes = serialize_msg(msg);
+            stream.write_all(serialized_bytes.as_bytes()).unwrap();
+
+            match read_resp(stream) {
+                Some(resp) => resp.contains("ok"),
+                None => false,
+            }
+        }
+        Err(e) => {
+            println!("Failed to Connect to Server: {:#?}", e);
+            false
+        }
+    }
+}
+
+//send a HEARTBEAT to a peer, returning the peer's reply
+fn send_heartbeat(peer: &SocketAddr, term: i32, commit_index: i32, commit_term: i32) -> Option<AppendEntryReplyPayload> {
+    match TcpStream::connect(peer) {
+        Ok(mut stream) => {
+            let msg = HeartbeatMessage {
+                src_addr: stream.local_addr().unwrap(),
+                src_id: calculate_hash(&stream.local_addr().unwrap()),
+                msg_type: MessageType::HEARTBEAT,
+                payload: HeartbeatPayload {
+                    term,
+                    leader: get_node_addr(),
+                    commit_index,
+                    commit_term,
+                },
+            };
+
+            let serialized_bytes = serde_json::to_string(&msg).unwrap();
+            stream.write_all(serialized_bytes.as_bytes()).unwrap();
+
+            let resp = read_resp(stream)?;
+            match serde_json::from_str::<AppendEntryReplyMessage>(&resp) {
+                Ok(reply) => Some(reply.payload),
+                Err(_) => None,
+            }
+        }
+        Err(_) => None,
+    }
+}
+
+//heartbeat every member of the cluster config, returning the peers that acknowledged this leader's term
+pub fn broadcast_heartbeat() -> Vec<SocketAddr> {
+    let term = get_hard_state().current_term;
+    let commit_index = get_commit_index();
+    let commit_term = entry_at(&get_raft_log(), commit_index).map_or(0, |entry| entry.leader_term);
+    let node_addr = get_node_addr();
+    let config = get_cluster_config();
+    let round_start = time::Instant::now();
+    let mut acks = Vec::new();
+    for peer in config.members() {
+        if peer == node_addr {
+            continue;
+        }
+        match send_heartbeat(&peer, term, commit_index, commit_term) {
+            Some(reply) if observe_term(reply.term) => return acks,
+            Some(reply) if reply.success => {
+                record_peer_ack(peer);
+                acks.push(peer);
+            }
+            _ => {}
+        }
+    }
+
+    let mut quorum_acks = acks.clone();
+    quorum_acks.push(node_addr);
+    if config.has_quorum(&quorum_acks) {
+        renew_lease(round_start);
+    }
+    acks
+}
+
+/*
+    followers that acknowledged a heartbeat will not vote for another candidate until an election timeout
+    after they received it, so the lease runs from when the round was sent, minus the clock drift margin
+ */
+fn renew_lease(round_start: time::Instant) {
+    *LEASE_EXPIRY.lock().unwrap() = Some(round_start + lease_duration(get_clock_drift_margin()));
+}
+
+pub fn has_valid_lease() -> bool {
+    match *LEASE_EXPIRY.lock().unwrap() {
+        Some(expiry) => is_leader() && time::Instant::now() < expiry,
+        None => false,
+    }
+}
+
+//ReadIndex leadership check: a heartbeat round acknowledged by a quorum proves no newer leader has been elected
+pub fn confirm_leadership() -> bool {
+    let mut acks = broadcast_heartbeat();
+    acks.push(get_node_addr());
+    is_leader() && get_cluster_config().has_quorum(&acks)
+}
+
+fn record_peer_ack(peer: SocketAddr) {
+    let mut last_peer_ack = LAST_PEER_ACK.lock().unwrap();
+    last_peer_ack.get_or_insert_with(HashMap::new).insert(peer, time::Instant::now());
+}
+
+//a new leader treats every member as recently heard from, giving followers one election timeout to respond
+fn reset_peer_acks() {
+    let now = time::Instant::now();
+    let acks = get_cluster_config().members().into_iter().map(|peer| (peer, now)).collect();
+    *LAST_PEER_ACK.lock().unwrap() = Some(acks);
+}
+
+/*
+    CheckQuorum: a leader that has not heard from a quorum within the election timeout may be on the
+    minority side of a partition, so it steps down instead of continuing to serve
+ */
+fn check_quorum() -> bool {
+    let timeout = time::Duration::from_millis(ELECTION_TIMEOUT);
+    let mut recent_acks = vec![get_node_addr()];
+    if let Some(last_peer_ack) = LAST_PEER_ACK.lock().unwrap().as_ref() {
+        for (peer, last_ack) in last_peer_ack {
+            if last_ack.elapsed() < timeout {
+                recent_acks.push(*peer);
+            }
+        }
+    }
+    get_cluster_config().has_quorum(&recent_acks)
+}
+
+//bring every member's log up to date with the leader's
+fn replicate_log() {
+    let node_addr = get_node_addr();
+    for peer in get_cluster_config().members() {
+        if peer != node_addr {
+            sync_peer(&peer);
+        }
+    }
+}
 
+/*
+    advance the commit index to the highest index stored on a quorum; only entries from the leader's
+    current term are committed by counting replicas, earlier entries are committed along with them
+ */
+pub fn advance_commit_index() {
+    let raft_log = get_raft_log();
+    let node_addr = get_node_addr();
+    let config = get_cluster_config();
 
+    let mut match_indexes = HashMap::new();
+    match_indexes.insert(node_addr, last_log_index(&raft_log));
+    for peer in config.members() {
+        if peer != node_addr {
+            match_indexes.insert(peer, get_peer_progress(&peer).match_index);
+        }
+    }
+
+    let quorum_index = config.quorum_match_index(&match_indexes);
+    if let Some(entry) = entry_at(&raft_log, quorum_index) {
+        if entry.leader_term == get_hard_state().current_term {
+            set_commit_index(quorum_index);
+        }
+    }
+    apply_committed_entries();
+}
+
+//leader appends a client command, replicates it, and waits for it to be committed and applied
+pub fn propose_command(command: LogCommand) -> Result<CommandResponse, String> {
+    if is_transferring_leadership() {
+        return Err(String::from("leadership transfer in progress"));
+    }
+    propose_batched(command)
+}
+
+/*
+    append a batch of proposed commands with one synced log write, replicate them together, and return each command's
+    result in order; an entry that is not committed fails, along with every entry after it
+ */
+pub fn commit_batch(commands: Vec<LogCommand>) -> Vec<Result<CommandResponse, String>> {
+    let entries = append_commands(get_node_addr().to_string(), commands);
+
+    replicate_log();
+    advance_commit_index();
+
+    entries.iter().map(|entry| {
+        let index = entry.prev_index + 1;
+        if get_last_applied() >= index {
+            Ok(take_applied_response(index).unwrap_or_else(|| CommandResponse::ok(None)))
+        } else {
+            Err(format!("entry {} was not committed by a quorum", index))
+        }
+    }).collect()
+}
+
+//client writes tagged with a session are deduplicated: a retry of the last request gets its cached response
+pub fn propose_client_command(command: LogCommand, session: Option<SessionTag>) -> Result<CommandResponse, String> {
+    match session {
+        Some(tag) => {
+            if let Some(response) = cached_session_response(tag.client_id, tag.sequence) {
+                return Ok(response);
+            }
+            propose_command(LogCommand::SessionCommand {
+                client_id: tag.client_id,
+                sequence: tag.sequence,
+                command: Box::new(command),
+            })
+        }
+        None => propose_command(command),
+    }
+}
+
+//KV_REQUEST, sent by the client CLI to the leader
+pub fn kv_request(dest_addr: String, kv_request: KvRequest) {
+    match TcpStream::connect(dest_addr) {
+        Ok(mut stream) => {
+            let msg = KvRequestMessage {
+                src_addr: stream.local_addr().unwrap(),
+                src_id: calculate_hash(&stream.local_addr().unwrap()),
+                msg_type: MessageType::KV_REQUEST,
+                payload: kv_request,
+            };
+
+            let serialized_bytes = serde_json::to_string(&msg).unwrap();
+            stream.write_all(serialized_bytes.as_bytes()).unwrap();
+
+            handle_resp(stream);
+        }
+        Err(e) => {
+            println!("Failed to Connect to Server: {:#?}", e)
+        }
+    }
+}
+
+//KV_REQUEST whose reply is returned to the caller instead of printed
+pub fn send_kv_request(dest_addr: &str, kv_request: KvRequest) -> Result<KvReply, String> {
+    let mut stream = TcpStream::connect(dest_addr).map_err(|e| e.to_string())?;
+    let msg = KvRequestMessage {
+        src_addr: stream.local_addr().unwrap(),
+        src_id: calculate_hash(&stream.local_addr().unwrap()),
+        msg_type: MessageType::KV_REQUEST,
+        payload: kv_request,
+    };
+    let serialized_bytes = serde_json::to_string(&msg).unwrap();
+    stream.write_all(serialized_bytes.as_bytes()).map_err(|e| e.to_string())?;
+
+    let mut resp = String::new();
+    stream.read_to_string(&mut resp).map_err(|e| e.to_string())?;
+    serde_json::from_str(&resp).map_err(|e| e.to_string())
+}
+
+/*
+    a new leader only counts replicas for entries of its own term, so it cannot tell which entries from earlier
+    terms are committed until one of its own commits. It appends a NoOp right away instead of waiting for a write
+ */
+fn append_term_start_noop() {
+    TERM_START_INDEX.store(i32::MAX, Ordering::SeqCst);
+    let entries = append_commands(get_node_addr().to_string(), vec![LogCommand::NoOp]);
+    TERM_START_INDEX.store(entries[0].prev_index + 1, Ordering::SeqCst);
+}
+
+//whether the NoOp from the start of this leader's term has committed
+pub fn has_committed_in_term() -> bool {
+    get_commit_index() >= TERM_START_INDEX.load(Ordering::SeqCst)
+}
+
+//a server that was elected (or started as leader) forgets the last term's leader state; the driver takes it from here
+pub fn start_leader_term() {
+    reset_replication_progress();
+    reset_peer_acks();
+    reset_lease_deadlines();
+    append_term_start_noop();
+    send_event(RaftEvent::BecameLeader);
+}
+
+//leader duties, run by the driver every heartbeat interval
+pub fn leader_tick() {
+    println!("doing leader stuff");
+
+    broadcast_heartbeat();
+    if !is_leader() {
+        return;
+    }
+    if !check_quorum() {
+        println!("CheckQuorum failed: no contact with a quorum within the election timeout, stepping down");
+        step_down();
+        return;
+    }
+
+    process_next_message();
+    retry_pending_config_change();
+    replicate_log();
+    advance_commit_index();
+    expire_leases();
+}
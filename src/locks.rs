@@ -0,0 +1,53 @@
+//module for distributed locks: held on key leases, with the acquiring log index as fencing token
+
+use std::{thread, time};
+use crate::log::LogCommand;
+use crate::leader::{propose_command, send_kv_request};
+use crate::key_leases::keep_alive_lease;
+use crate::message::KvRequest;
+use crate::state_machine::{get_locks, CommandResponse};
+
+//how long a waiting client sleeps before trying to acquire a held lock again
+const LOCK_RETRY_INTERVAL: u64 = 1000;
+
+fn check_lock_holder(name: &str, token: u64) -> Result<(), String> {
+    match get_locks().get(name) {
+        Some(held) if *held == token => Ok(()),
+        Some(held) => Err(format!("lock {} is held with token {}, not {}", name, held, token)),
+        None => Err(format!("lock {} is not held", name)),
+    }
+}
+
+//renewing restarts the ttl of the lock's lease on the leader
+pub fn renew_lock(name: &str, token: u64) -> Result<CommandResponse, String> {
+    check_lock_holder(name, token)?;
+    keep_alive_lease(token)
+}
+
+//releasing revokes the lock's lease; a stale token names a lease that is already gone, so it can not release a newer holder
+pub fn release_lock(name: &str, token: u64) -> Result<CommandResponse, String> {
+    check_lock_holder(name, token)?;
+    propose_command(LogCommand::RevokeLease { id: token })
+}
+
+/*
+    client side of lock acquire: prints the reply, whose value is the fencing token; with wait, retries until
+    the lock is free. A holder has to renew within the ttl and pass its token along with every write it makes
+ */
+pub fn acquire_lock_request(dest_addr: String, name: String, ttl: u64, wait: bool) {
+    loop {
+        let reply = match send_kv_request(&dest_addr, KvRequest::AcquireLock { name: name.clone(), ttl }) {
+            Ok(reply) => reply,
+            Err(e) => {
+                println!("Failed to acquire lock {}: {}", name, e);
+                return;
+            }
+        };
+        println!("{:#?}", reply);
+        //only a held lock is worth waiting for; other failures, like not reaching the leader, are not retried
+        if reply.success || !wait || !reply.reason.contains("is held") {
+            return;
+        }
+        thread::sleep(time::Duration::from_millis(LOCK_RETRY_INTERVAL));
+    }
+}
@@ -2,8 +2,43 @@ use serde::{Serialize, Deserialize};
 use std::hash::{Hash, Hasher};
 use std::collections::hash_map::DefaultHasher;
 use std::collections::VecDeque;
+use std::net::SocketAddr;
 use crate::message::AppendEntryRequest;
-use crate::kv_store::{set_key, get_key};
+use crate::storage::{LogStorage, with_log_storage};
+use crate::membership::ClusterConfig;
+use crate::state_machine::Txn;
+use crate::mvcc::KvData;
+
+//commands carried by a log entry
+#[derive(Serialize, Deserialize, Debug, Clone, Hash)]
+pub enum LogCommand {
+    ConfigChange(ClusterConfig),
+    Set {
+        key: String,
+        value: String,
+        //key lease the key is attached to; the key is deleted when the lease expires or is revoked
+        #[serde(default)]
+        lease: Option<u64>,
+    },
+    Delete { key: String },
+    CompareAndSwap { key: String, expected: String, value: String },
+    SetIfAbsent { key: String, value: String },
+    DeleteIfVersion { key: String, version: u64 },
+    Txn(Txn),
+    GrantLease { ttl: u64 },
+    RevokeLease { id: u64 },
+    Compact { revision: u64 },
+    AcquireLock { name: String, ttl: u64 },
+    //a key command applied in a namespace other than the default one
+    Namespaced { namespace: String, command: Box<LogCommand> },
+    CreateNamespace { namespace: String },
+    DropNamespace { namespace: String },
+    RestoreNamespace { namespace: String, kv_data: KvData },
+    RegisterSession,
+    //appended by a new leader so an entry from its own term commits; changes nothing when applied
+    NoOp,
+    SessionCommand { client_id: u64, sequence: u64, command: Box<LogCommand> },
+}
 
 #[derive(Serialize, Deserialize, Debug, Clone, Hash)]
 pub struct LogEntry {
@@ -12,25 +47,194 @@ pub struct LogEntry {
     pub prev_index: i32,
     pub prev_term: i32,
     pub leader_commit_index: i32,
+    #[serde(default)]
+    pub command: Option<LogCommand>,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone, Hash)]
+#[derive(Serialize, Deserialize, Debug, Clone, Hash, Default)]
 pub struct TheLog {
     pub log_entries: VecDeque<LogEntry>,
 }
 
-//initialize the serialized raft_log in the kv store
-pub fn initialize_raft_log() {
-    let mut raft_log = TheLog {
-        log_entries: Default::default()
-    };
-    let serialized: String = serde_json::to_string(&raft_log).unwrap();
-    set_key(String::from("raft_log"), serialized);
+//persistent election state: the latest term this server has seen, and who it voted for in that term
+#[derive(Serialize, Deserialize, Debug, Clone, Hash, PartialEq, Default)]
+pub struct HardState {
+    pub current_term: i32,
+    pub voted_for: Option<SocketAddr>,
+}
+
+//the log a server kept before it restarted is used as is; returns the index of its last entry
+pub fn load_raft_log() -> i32 {
+    with_log_storage(|storage| storage.last_index())
+}
+
+/*
+    the term and vote a server stored before a restart are kept, so it never votes twice in one term. A server
+    started as leader takes the next term with its own vote in it, so no other server can be elected in that term
+ */
+pub fn load_hard_state(bootstrap_leader: Option<SocketAddr>) -> HardState {
+    let mut hard_state = get_hard_state();
+    if let Some(leader) = bootstrap_leader {
+        hard_state = HardState {
+            current_term: hard_state.current_term + 1,
+            voted_for: Some(leader),
+        };
+        set_hard_state(hard_state.clone());
+    }
+    hard_state
+}
+
+//move to a newer term, with no vote cast in it yet
+pub fn initialize_hard_state(current_term: i32) {
+    set_hard_state(HardState {
+        current_term,
+        voted_for: None,
+    });
 }
 
-//getter for raft_log
+//getter for hard_state
+pub fn get_hard_state() -> HardState {
+    with_log_storage(|storage| storage.hard_state())
+}
+
+pub fn set_hard_state(hard_state: HardState) {
+    with_log_storage(|storage| storage.save_hard_state(&hard_state));
+}
+
+//getter for raft_log: a copy of every entry in the log storage
 pub fn get_raft_log() -> TheLog {
-    serde_json::from_str(&get_key(String::from("raft_log"))).unwrap()
+    with_log_storage(|storage| TheLog {
+        log_entries: storage.entries(1..storage.last_index() + 1).into(),
+    })
+}
+
+//index of the last entry in the log; entries are indexed from 1
+pub fn last_log_index(raft_log: &TheLog) -> i32 {
+    raft_log.log_entries.len() as i32
+}
+
+//term of the last entry in the log, 0 for an empty log
+pub fn last_log_term(raft_log: &TheLog) -> i32 {
+    match raft_log.log_entries.back() {
+        Some(entry) => entry.leader_term,
+        None => 0,
+    }
+}
+
+//leader appends a new command to the end of its own raft_log
+pub fn append_command(leader_id: String, command: LogCommand) -> LogEntry {
+    append_commands(leader_id, vec![command]).pop().unwrap()
+}
+
+//leader appends a batch of commands to its raft_log with a single write (and sync), in order
+pub fn append_commands(leader_id: String, commands: Vec<LogCommand>) -> Vec<LogEntry> {
+    let leader_term = get_hard_state().current_term.max(1);
+    with_log_storage(|storage| {
+        let last_index = storage.last_index();
+        let last_term = storage.term(last_index).unwrap_or(0);
+        let entries: Vec<LogEntry> = commands.into_iter().enumerate().map(|(position, command)| {
+            let prev_index = last_index + position as i32;
+            LogEntry {
+                leader_term,
+                leader_id: leader_id.clone(),
+                prev_index,
+                prev_term: if position == 0 { last_term } else { leader_term },
+                leader_commit_index: prev_index + 1,
+                command: Some(command),
+            }
+        }).collect();
+        storage.append(&entries);
+        entries
+    })
+}
+
+/*
+    follower side of replication: a batch of consecutive entries is placed after the first entry's prev_index
+    if the log contains a matching entry there; a conflicting entry (and everything after it) is replaced
+ */
+pub fn append_replicated_entries(storage: &mut dyn LogStorage, entries: &[LogEntry]) -> bool {
+    let first = match entries.first() {
+        Some(first) => first,
+        None => return true,
+    };
+    if first.prev_index > storage.last_index() || first.prev_index < 0 {
+        return false;
+    }
+    if storage.term(first.prev_index) != Some(first.prev_term) {
+        return false;
+    }
+    /*
+        an entry the log already has at the same index with the same term is the same entry, and is skipped;
+        from the first index whose term differs on, the log is replaced in one write
+     */
+    let start = first.prev_index + 1;
+    let existing = storage.entries(start..start + entries.len() as i32);
+    let matching = existing.iter().zip(entries)
+        .take_while(|(existing, entry)| existing.leader_term == entry.leader_term)
+        .count();
+    if matching == entries.len() {
+        return true;
+    }
+    if matching < existing.len() {
+        storage.truncate_suffix(start + matching as i32);
+    }
+    storage.append(&entries[matching..]);
+    true
+}
+
+/*
+    where a rejected batch conflicts with the follower's log, as (conflict_term, conflict_index): the term of its
+    entry at prev_index and the first index it has for that term, or no term and the index after its last entry
+    when the log does not reach prev_index. The leader can skip back past a whole term in one round-trip
+ */
+pub fn conflict_hint(storage: &dyn LogStorage, prev_index: i32) -> (Option<i32>, i32) {
+    if prev_index > storage.last_index() {
+        return (None, storage.last_index() + 1);
+    }
+    match storage.term(prev_index) {
+        Some(conflict_term) => {
+            let mut conflict_index = prev_index;
+            while conflict_index > 1 && storage.term(conflict_index - 1) == Some(conflict_term) {
+                conflict_index -= 1;
+            }
+            (Some(conflict_term), conflict_index)
+        }
+        None => (None, prev_index.max(1)),
+    }
+}
+
+/*
+    leader side of a rejection: the next index to send a peer after the batch starting at sent_from was rejected.
+    If the leader has entries of the conflicting term, the peer's log can match up to the last of them; otherwise
+    that whole term is skipped. A peer that sent no hint is walked back one entry
+ */
+pub fn next_index_after_conflict(storage: &dyn LogStorage, sent_from: i32, conflict_term: Option<i32>,
+                                 conflict_index: Option<i32>) -> i32 {
+    let next_index = match (conflict_term, conflict_index) {
+        (Some(conflict_term), Some(conflict_index)) => {
+            let mut index = (sent_from - 1).min(storage.last_index());
+            while index > 0 && storage.term(index).is_some_and(|term| term > conflict_term) {
+                index -= 1;
+            }
+            if index > 0 && storage.term(index) == Some(conflict_term) {
+                index + 1
+            } else {
+                conflict_index
+            }
+        }
+        (None, Some(conflict_index)) => conflict_index,
+        _ => sent_from - 1,
+    };
+    //always move back, so a bad hint cannot stall the peer
+    next_index.min(sent_from - 1).max(1)
+}
+
+//entry at the given index, if the log has one
+pub fn entry_at(raft_log: &TheLog, index: i32) -> Option<LogEntry> {
+    if index < 1 {
+        return None;
+    }
+    raft_log.log_entries.get((index - 1) as usize).cloned()
 }
 
 pub fn validate_log_entry(entry: LogEntry, mut raft_log: TheLog) -> bool {
@@ -49,17 +253,6 @@ pub fn validate_log_entry(entry: LogEntry, mut raft_log: TheLog) -> bool {
     true
 }
 
-fn append_entry(entry: LogEntry, log_entries: VecDeque<LogEntry>) -> Result<TheLog, bool> {
-
-    let entry_clone = entry.clone();
-    let mut log_entries_clone = log_entries.clone();
-
-    log_entries_clone.push_back(entry_clone);
-
-    //return a new Log with cloned entries
-    Ok(TheLog{log_entries: log_entries_clone})
-}
-
 /*
     1) the log is never allowed to have holes in it.
  */
@@ -86,15 +279,6 @@ fn check_prev_term(entry: LogEntry, log: VecDeque<LogEntry>) -> bool {
     }
 }
 
-/*
-    3) Appending log entries at index 0 always works. This is the start of the log.
-    5) Calling append_entry() with an empty list of entries is allowed
- */
-fn check_empty_log(log_entries: VecDeque<LogEntry>) -> bool {
-    log_entries.is_empty() || log_entries.len() == 0
-}
-
-
 /*
     4) Log appends are idempotent; using hash comparisons
  */
@@ -110,25 +294,6 @@ fn is_duplicate_entry(entry: LogEntry, log: VecDeque<LogEntry>) -> bool {
     duplicate_entry
 }
 
-/*
-    6) If there are already existing entries, but those entries are from an earlier term,
-    the existing entries and everything that follows are deleted, then the new entries are added in their
-    place.
- */
-fn is_entry_earlier_term(entry: LogEntry, log: VecDeque<LogEntry>) -> bool {
-    let mut log_clone = log.clone();
-    if entry.leader_term < log_clone.pop_back().unwrap().leader_term {
-        return true;
-    }
-    return false;
-}
-fn replace_existing_entries_earlier_term(entry: LogEntry) -> VecDeque<LogEntry> {
-    let mut new_log: VecDeque<LogEntry> = VecDeque::new();
-    new_log.push_back(entry);
-    new_log
-}
-
-
 fn calculate_hash<T: Hash> (t: &T) -> u64 {
     let mut hasher = DefaultHasher::new();
     t.hash(&mut hasher);
@@ -138,31 +303,7 @@ fn calculate_hash<T: Hash> (t: &T) -> u64 {
 #[cfg(test)]
 mod tests {
     use super::*;
-
-    #[test]
-    fn test_empty_log() {
-        let empty_log: VecDeque<LogEntry> = VecDeque::new();
-        assert_eq!(check_empty_log(empty_log), true);
-    }
-
-    #[test]
-    fn test_empty_log_append() {
-        let empty_log: VecDeque<LogEntry> = VecDeque::new();
-
-        if check_empty_log(empty_log.clone()) {
-            let _log_entry = LogEntry{
-                leader_term: 0,
-                leader_id: String::from("test"),
-                prev_index: 0,
-                prev_term: 0,
-                leader_commit_index: 0,
-            };
-
-            let updated_log = append_entry(_log_entry, empty_log).unwrap();
-            println!("{:#?}", updated_log); //print out the log
-            assert_eq!(updated_log.log_entries.len(), 1);
-        }
-    }
+    use crate::storage::MemoryLogStorage;
 
     #[test]
     fn test_no_holes() {
@@ -174,6 +315,7 @@ mod tests {
             prev_index: 0,
             prev_term: 0,
             leader_commit_index: 0,
+            command: None,
         };
         log.push_back(log_entry);
 
@@ -183,6 +325,7 @@ mod tests {
             prev_index: 5,
             prev_term: 0,
             leader_commit_index: 5,
+            command: None,
         };
 
         assert_eq!(check_no_holes_allowed(bad_entry, log), false)
@@ -196,6 +339,7 @@ mod tests {
             prev_index: 0,
             prev_term: 0,
             leader_commit_index: 0,
+            command: None,
         };
         let duplicate_log_entry = LogEntry{
             leader_term: 0,
@@ -203,6 +347,7 @@ mod tests {
             prev_index: 0,
             prev_term: 0,
             leader_commit_index: 0,
+            command: None,
         };
 
         let log_entry_hash = calculate_hash(&log_entry);
@@ -225,6 +370,7 @@ mod tests {
             prev_index: 0,
             prev_term: 0,
             leader_commit_index: 1,
+            command: None,
         };
         let mut log: VecDeque<LogEntry> = VecDeque::new();
 
@@ -236,76 +382,149 @@ mod tests {
             prev_index: 1,
             prev_term: 0,
             leader_commit_index: 1,
+            command: None,
         };
 
         assert_eq!(check_prev_term(new_entry, log), true)
     }
 
+    fn replicated_entry(term: i32, prev_index: i32, prev_term: i32) -> LogEntry {
+        LogEntry{
+            leader_term: term,
+            leader_id: String::from("leader"),
+            prev_index,
+            prev_term,
+            leader_commit_index: prev_index + 1,
+            command: None,
+        }
+    }
+
     #[test]
-    fn test_check_entries_earlier_term() {
-        let log_entry_1 = LogEntry{
-            leader_term: 5,
-            leader_id: String::from("test"),
-            prev_index: 5,
-            prev_term: 5,
-            leader_commit_index: 5,
-        };
+    fn test_append_replicated_entries() {
+        let mut storage = MemoryLogStorage::default();
+
+        assert!(append_replicated_entries(&mut storage, &[replicated_entry(1, 0, 0)]));
+        assert!(append_replicated_entries(&mut storage, &[replicated_entry(1, 1, 1)]));
+        //re-sending an entry is idempotent
+        assert!(append_replicated_entries(&mut storage, &[replicated_entry(1, 1, 1)]));
+        assert_eq!(storage.last_index(), 2);
+
+        //an entry matching the log's term at its index is skipped, whatever else differs, and nothing is truncated
+        assert!(append_replicated_entries(&mut storage, &[replicated_entry(1, 2, 1)]));
+        let resent_entry = LogEntry{ leader_commit_index: 3, ..replicated_entry(1, 1, 1) };
+        assert!(append_replicated_entries(&mut storage, &[resent_entry]));
+        assert_eq!(storage.last_index(), 3);
+
+        //holes and mismatched prev_term are rejected
+        assert!(!append_replicated_entries(&mut storage, &[replicated_entry(1, 5, 1)]));
+        assert!(!append_replicated_entries(&mut storage, &[replicated_entry(2, 2, 2)]));
+    }
 
-        let log_entry_0 = LogEntry{
-            leader_term: 2,
-            leader_id: String::from("test"),
-            prev_index: 1,
-            prev_term: 1,
-            leader_commit_index: 2,
-        };
+    #[test]
+    fn test_replicated_entry_replaces_conflicts() {
+        let mut storage = MemoryLogStorage::default();
+        append_replicated_entries(&mut storage, &[replicated_entry(1, 0, 0)]);
+        append_replicated_entries(&mut storage, &[replicated_entry(1, 1, 1)]);
+        append_replicated_entries(&mut storage, &[replicated_entry(1, 2, 1)]);
+
+        //a new leader's entry at index 2 removes the old entries at 2 and 3
+        assert!(append_replicated_entries(&mut storage, &[replicated_entry(2, 1, 1)]));
+        assert_eq!(storage.last_index(), 2);
+        assert_eq!(storage.term(2), Some(2));
+    }
 
-        let mut log: VecDeque<LogEntry> = VecDeque::new();
-        log.push_back(log_entry_0);
-        log.push_back(log_entry_1);
-
-        let new_log_entry = LogEntry{
-            leader_term: 3,
-            leader_id: String::from("adifferentid"),
-            prev_index: 3,
-            prev_term: 3,
-            leader_commit_index: 3,
-        };
+    #[test]
+    fn test_append_replicated_batch() {
+        let mut storage = MemoryLogStorage::default();
+        let batch = [replicated_entry(1, 0, 0), replicated_entry(1, 1, 1), replicated_entry(1, 2, 1)];
+        assert!(append_replicated_entries(&mut storage, &batch));
+        assert_eq!(storage.last_index(), 3);
+
+        //a resent batch overlapping the log only replaces from the first conflicting entry
+        let batch = [replicated_entry(1, 1, 1), replicated_entry(2, 2, 1), replicated_entry(2, 3, 2)];
+        assert!(append_replicated_entries(&mut storage, &batch));
+        assert_eq!(storage.last_index(), 4);
+        assert_eq!(storage.term(2), Some(1));
+        assert_eq!(storage.term(3), Some(2));
+    }
 
-        assert_eq!(is_entry_earlier_term(new_log_entry, log), true);
+    //a log with one entry per term in terms, each entry pointing at the one before it
+    fn log_with_terms(terms: &[i32]) -> MemoryLogStorage {
+        let mut storage = MemoryLogStorage::default();
+        let mut prev_term = 0;
+        for (index, term) in terms.iter().enumerate() {
+            storage.append(&[replicated_entry(*term, index as i32, prev_term)]);
+            prev_term = *term;
+        }
+        storage
     }
 
-    #[test]
-    fn test_replace_entries_earlier_term() {
-        let log_entry_1 = LogEntry{
-            leader_term: 5,
-            leader_id: String::from("test"),
-            prev_index: 5,
-            prev_term: 5,
-            leader_commit_index: 5,
-        };
+    //replicate the leader's log to the follower using conflict hints; returns the number of round-trips
+    fn sync_with_hints(leader: &MemoryLogStorage, follower: &mut MemoryLogStorage) -> i32 {
+        let mut next_index = leader.last_index();
+        let mut round_trips = 0;
+        loop {
+            round_trips += 1;
+            let entries = leader.entries(next_index..leader.last_index() + 1);
+            if append_replicated_entries(follower, &entries) {
+                return round_trips;
+            }
+            let (conflict_term, conflict_index) = conflict_hint(follower, entries[0].prev_index);
+            next_index = next_index_after_conflict(leader, next_index, conflict_term, Some(conflict_index));
+        }
+    }
 
-        let log_entry_0 = LogEntry{
-            leader_term: 2,
-            leader_id: String::from("test"),
-            prev_index: 1,
-            prev_term: 1,
-            leader_commit_index: 2,
-        };
+    fn terms(storage: &MemoryLogStorage) -> Vec<i32> {
+        storage.entries(1..storage.last_index() + 1).iter().map(|entry| entry.leader_term).collect()
+    }
 
-        let mut log: VecDeque<LogEntry> = VecDeque::new();
-        log.push_back(log_entry_0);
-        log.push_back(log_entry_1);
-
-        let new_log_entry = LogEntry{
-            leader_term: 3,
-            leader_id: String::from("adifferentid"),
-            prev_index: 3,
-            prev_term: 3,
-            leader_commit_index: 3,
-        };
+    #[test]
+    fn test_conflict_hint() {
+        let follower = log_with_terms(&[1, 1, 2, 2, 2, 3]);
+        assert_eq!(conflict_hint(&follower, 5), (Some(2), 3));
+        assert_eq!(conflict_hint(&follower, 6), (Some(3), 6));
+        assert_eq!(conflict_hint(&follower, 9), (None, 7));
+    }
 
-       let replaced_log: VecDeque<LogEntry> = replace_existing_entries_earlier_term(new_log_entry);
-        assert_eq!(replaced_log.is_empty(), false)
+    #[test]
+    fn test_next_index_after_conflict() {
+        let leader = log_with_terms(&[1, 1, 2, 2, 4, 4]);
+        //the leader has term 2 up to index 4, so the peer can match up to there
+        assert_eq!(next_index_after_conflict(&leader, 6, Some(2), Some(3)), 5);
+        //the leader has no term 3 entries; the peer's whole term is skipped
+        assert_eq!(next_index_after_conflict(&leader, 6, Some(3), Some(4)), 4);
+        assert_eq!(next_index_after_conflict(&leader, 6, None, Some(2)), 2);
+        assert_eq!(next_index_after_conflict(&leader, 6, None, None), 5);
+        assert_eq!(next_index_after_conflict(&leader, 1, None, None), 1);
     }
 
+    #[test]
+    fn test_long_divergent_log() {
+        //the follower was leader in term 2 and appended 100 entries no one else has
+        let mut leader_terms = vec![1; 3];
+        leader_terms.extend(vec![3; 100]);
+        let mut follower_terms = vec![1; 3];
+        follower_terms.extend(vec![2; 100]);
+        let leader = log_with_terms(&leader_terms);
+        let mut follower = log_with_terms(&follower_terms);
+
+        assert_eq!(sync_with_hints(&leader, &mut follower), 2);
+        assert_eq!(terms(&follower), leader_terms);
+    }
+
+    #[test]
+    fn test_long_lagging_log() {
+        let mut leader_terms = vec![1; 3];
+        leader_terms.extend(vec![2; 50]);
+        leader_terms.extend(vec![5; 50]);
+        let mut follower_terms = vec![1; 3];
+        follower_terms.extend(vec![2; 20]);
+        follower_terms.extend(vec![4; 40]);
+        let leader = log_with_terms(&leader_terms);
+        let mut follower = log_with_terms(&follower_terms);
+
+        //one round-trip finds the follower's log is short, one skips its term 4 entries
+        assert_eq!(sync_with_hints(&leader, &mut follower), 3);
+        assert_eq!(terms(&follower), leader_terms);
+    }
 }
\ No newline at end of file
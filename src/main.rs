@@ -1,6 +1,16 @@
 use crate::message::{get_dummy_append_entry_req, get_dummy_request_vote};
+use crate::watch::watch_request;
+use crate::locks::acquire_lock_request;
+use crate::namespaces::{snapshot_namespace_request, restore_namespace_request};
 use crate::leader::{append_entry_request, send_healthcheck_message};
 use crate::server::{setup_tcp_listener, request_vote};
+use crate::leader::{config_change_request, transfer_leader_request, kv_request};
+use crate::message::KvRequest;
+use crate::reads::DEFAULT_CLOCK_DRIFT_MARGIN;
+use crate::group_commit::{set_batching, DEFAULT_MAX_BATCH_SIZE, DEFAULT_MAX_BATCH_LINGER};
+use crate::flow_control::{set_flow_control, DEFAULT_MAX_INFLIGHT_MSGS, DEFAULT_MAX_INFLIGHT_BYTES,
+                           DEFAULT_MAX_PENDING_PROPOSALS};
+use crate::membership::ConfigChange;
 
 //local modules
 mod kv_store;
@@ -9,6 +19,19 @@ mod cmd;
 mod log;
 mod message;
 mod leader;
+mod membership;
+mod election;
+mod state_machine;
+mod reads;
+mod key_leases;
+mod watch;
+mod mvcc;
+mod locks;
+mod namespaces;
+mod storage;
+mod group_commit;
+mod flow_control;
+mod driver;
 mod server;
 
 fn main() {
@@ -19,7 +42,25 @@ fn main() {
     //Run TCP Server Listener
     if let ("server", Some(_server_matches)) = matches.subcommand() {
         let is_leader = _server_matches.is_present("is_leader");
-        setup_tcp_listener(is_leader);
+        let bind_address = _server_matches.value_of("address").map(|address| address.parse().unwrap());
+        let pre_vote = _server_matches.is_present("pre_vote");
+        let lease_read = _server_matches.is_present("lease_read");
+        let clock_drift_margin = _server_matches.value_of("clock_drift")
+            .map_or(DEFAULT_CLOCK_DRIFT_MARGIN, |margin| margin.parse().unwrap());
+        let in_memory_log = _server_matches.is_present("in_memory_log");
+        let max_batch_size = _server_matches.value_of("max_batch_size")
+            .map_or(DEFAULT_MAX_BATCH_SIZE, |size| size.parse().unwrap());
+        let max_batch_linger = _server_matches.value_of("max_batch_linger")
+            .map_or(DEFAULT_MAX_BATCH_LINGER, |linger| linger.parse().unwrap());
+        set_batching(max_batch_size, max_batch_linger);
+        let max_inflight_msgs = _server_matches.value_of("max_inflight_msgs")
+            .map_or(DEFAULT_MAX_INFLIGHT_MSGS, |msgs| msgs.parse().unwrap());
+        let max_inflight_bytes = _server_matches.value_of("max_inflight_bytes")
+            .map_or(DEFAULT_MAX_INFLIGHT_BYTES, |bytes| bytes.parse().unwrap());
+        let max_pending_proposals = _server_matches.value_of("max_pending_proposals")
+            .map_or(DEFAULT_MAX_PENDING_PROPOSALS, |proposals| proposals.parse().unwrap());
+        set_flow_control(max_inflight_msgs, max_inflight_bytes, max_pending_proposals);
+        setup_tcp_listener(is_leader, bind_address, pre_vote, lease_read, clock_drift_margin, in_memory_log);
     }
 
     //client CLI wrapper for easy testing of message sending
@@ -45,5 +86,196 @@ fn main() {
             let args = cmd::get_address(request_matches);
             request_vote(args.address, get_dummy_request_vote());
         }
+
+        //CONFIG_CHANGE messages
+        if let ("add-node", Some(add_node_matches)) = client_matches.subcommand() {
+            cmd::print_address(add_node_matches);
+            let args = cmd::get_address(add_node_matches);
+            config_change_request(args.address, ConfigChange::AddNode(cmd::get_node(add_node_matches)));
+        }
+        if let ("remove-node", Some(remove_node_matches)) = client_matches.subcommand() {
+            cmd::print_address(remove_node_matches);
+            let args = cmd::get_address(remove_node_matches);
+            config_change_request(args.address, ConfigChange::RemoveNode(cmd::get_node(remove_node_matches)));
+        }
+        if let ("add-learner", Some(add_learner_matches)) = client_matches.subcommand() {
+            cmd::print_address(add_learner_matches);
+            let args = cmd::get_address(add_learner_matches);
+            config_change_request(args.address, ConfigChange::AddLearner(cmd::get_node(add_learner_matches)));
+        }
+        if let ("promote", Some(promote_matches)) = client_matches.subcommand() {
+            cmd::print_address(promote_matches);
+            let args = cmd::get_address(promote_matches);
+            config_change_request(args.address, ConfigChange::PromoteLearner(cmd::get_node(promote_matches)));
+        }
+
+        //KV_REQUEST messages
+        if let ("set", Some(set_matches)) = client_matches.subcommand() {
+            cmd::print_address(set_matches);
+            let args = cmd::get_kv_args(set_matches);
+            let session = cmd::get_session(set_matches);
+            let ttl = set_matches.value_of("ttl").map(|ttl| ttl.parse().unwrap());
+            let lease = set_matches.value_of("lease").map(|lease| lease.parse().unwrap());
+            kv_request(args.address, cmd::in_namespace(set_matches, KvRequest::Set { key: args.key, value: args.value, session, ttl, lease }));
+        }
+        if let ("get", Some(get_matches)) = client_matches.subcommand() {
+            cmd::print_address(get_matches);
+            let args = cmd::get_kv_args(get_matches);
+            let consistency = cmd::get_read_consistency(get_matches);
+            let revision = cmd::get_revision(get_matches);
+            kv_request(args.address, cmd::in_namespace(get_matches, KvRequest::Get { key: args.key, consistency, revision }));
+        }
+        if let ("scan", Some(scan_matches)) = client_matches.subcommand() {
+            cmd::print_address(scan_matches);
+            let args = cmd::get_address(scan_matches);
+            kv_request(args.address, cmd::in_namespace(scan_matches, KvRequest::Scan {
+                start: scan_matches.value_of("start").unwrap().to_string(),
+                end: scan_matches.value_of("end").map(String::from),
+                limit: cmd::get_scan_limit(scan_matches),
+                consistency: cmd::get_read_consistency(scan_matches),
+                revision: cmd::get_revision(scan_matches),
+            }));
+        }
+        if let ("list", Some(list_matches)) = client_matches.subcommand() {
+            cmd::print_address(list_matches);
+            let args = cmd::get_address(list_matches);
+            kv_request(args.address, cmd::in_namespace(list_matches, KvRequest::List {
+                prefix: list_matches.value_of("prefix").unwrap().to_string(),
+                start: list_matches.value_of("start").map(String::from),
+                limit: cmd::get_scan_limit(list_matches),
+                consistency: cmd::get_read_consistency(list_matches),
+                revision: cmd::get_revision(list_matches),
+            }));
+        }
+        if let ("delete", Some(delete_matches)) = client_matches.subcommand() {
+            cmd::print_address(delete_matches);
+            let args = cmd::get_kv_args(delete_matches);
+            let session = cmd::get_session(delete_matches);
+            kv_request(args.address, cmd::in_namespace(delete_matches, KvRequest::Delete { key: args.key, session }));
+        }
+        if let ("cas", Some(cas_matches)) = client_matches.subcommand() {
+            cmd::print_address(cas_matches);
+            let args = cmd::get_kv_args(cas_matches);
+            let expected = cas_matches.value_of("expected").unwrap().to_string();
+            let session = cmd::get_session(cas_matches);
+            let request = KvRequest::CompareAndSwap { key: args.key, expected, value: args.value, session };
+            kv_request(args.address, cmd::in_namespace(cas_matches, request));
+        }
+        if let ("set-if-absent", Some(set_matches)) = client_matches.subcommand() {
+            cmd::print_address(set_matches);
+            let args = cmd::get_kv_args(set_matches);
+            let session = cmd::get_session(set_matches);
+            kv_request(args.address, cmd::in_namespace(set_matches, KvRequest::SetIfAbsent { key: args.key, value: args.value, session }));
+        }
+        if let ("delete-if-version", Some(delete_matches)) = client_matches.subcommand() {
+            cmd::print_address(delete_matches);
+            let args = cmd::get_kv_args(delete_matches);
+            let version = delete_matches.value_of("version").unwrap().parse().unwrap();
+            let session = cmd::get_session(delete_matches);
+            kv_request(args.address, cmd::in_namespace(delete_matches, KvRequest::DeleteIfVersion { key: args.key, version, session }));
+        }
+        if let ("txn", Some(txn_matches)) = client_matches.subcommand() {
+            cmd::print_address(txn_matches);
+            let args = cmd::get_address(txn_matches);
+            let session = cmd::get_session(txn_matches);
+            kv_request(args.address, cmd::in_namespace(txn_matches, KvRequest::Txn { txn: cmd::get_txn(txn_matches), session }));
+        }
+        if let ("lease-grant", Some(lease_matches)) = client_matches.subcommand() {
+            cmd::print_address(lease_matches);
+            let args = cmd::get_address(lease_matches);
+            let ttl = lease_matches.value_of("ttl").unwrap().parse().unwrap();
+            kv_request(args.address, KvRequest::GrantLease { ttl });
+        }
+        if let ("lease-keepalive", Some(lease_matches)) = client_matches.subcommand() {
+            cmd::print_address(lease_matches);
+            let args = cmd::get_address(lease_matches);
+            kv_request(args.address, KvRequest::KeepAliveLease { id: cmd::get_lease_id(lease_matches) });
+        }
+        if let ("lease-revoke", Some(lease_matches)) = client_matches.subcommand() {
+            cmd::print_address(lease_matches);
+            let args = cmd::get_address(lease_matches);
+            kv_request(args.address, KvRequest::RevokeLease { id: cmd::get_lease_id(lease_matches) });
+        }
+        if let ("watch", Some(watch_matches)) = client_matches.subcommand() {
+            cmd::print_address(watch_matches);
+            let args = cmd::get_kv_args(watch_matches);
+            let mut addresses = vec![args.address];
+            addresses.extend(watch_matches.values_of("failover").into_iter().flatten().map(String::from));
+            let start_index = watch_matches.value_of("from").map_or(0, |from| from.parse().unwrap());
+            let namespace = cmd::get_namespace(watch_matches);
+            watch_request(addresses, namespace, args.key, watch_matches.is_present("prefix"), start_index);
+        }
+        if let ("compact", Some(compact_matches)) = client_matches.subcommand() {
+            cmd::print_address(compact_matches);
+            let args = cmd::get_address(compact_matches);
+            let revision = cmd::get_revision(compact_matches).unwrap();
+            kv_request(args.address, KvRequest::Compact { revision });
+        }
+        if let ("lock", Some(lock_matches)) = client_matches.subcommand() {
+            if let ("acquire", Some(acquire_matches)) = lock_matches.subcommand() {
+                cmd::print_address(acquire_matches);
+                let args = cmd::get_address(acquire_matches);
+                let name = acquire_matches.value_of("name").unwrap().to_string();
+                let ttl = acquire_matches.value_of("ttl").unwrap().parse().unwrap();
+                acquire_lock_request(args.address, name, ttl, acquire_matches.is_present("wait"));
+            }
+            if let ("renew", Some(renew_matches)) = lock_matches.subcommand() {
+                cmd::print_address(renew_matches);
+                let args = cmd::get_address(renew_matches);
+                let name = renew_matches.value_of("name").unwrap().to_string();
+                kv_request(args.address, KvRequest::RenewLock { name, token: cmd::get_lock_token(renew_matches) });
+            }
+            if let ("release", Some(release_matches)) = lock_matches.subcommand() {
+                cmd::print_address(release_matches);
+                let args = cmd::get_address(release_matches);
+                let name = release_matches.value_of("name").unwrap().to_string();
+                kv_request(args.address, KvRequest::ReleaseLock { name, token: cmd::get_lock_token(release_matches) });
+            }
+        }
+        if let ("namespace", Some(namespace_matches)) = client_matches.subcommand() {
+            if let ("create", Some(create_matches)) = namespace_matches.subcommand() {
+                cmd::print_address(create_matches);
+                let args = cmd::get_address(create_matches);
+                let namespace = create_matches.value_of("name").unwrap().to_string();
+                kv_request(args.address, KvRequest::CreateNamespace { namespace });
+            }
+            if let ("drop", Some(drop_matches)) = namespace_matches.subcommand() {
+                cmd::print_address(drop_matches);
+                let args = cmd::get_address(drop_matches);
+                let namespace = drop_matches.value_of("name").unwrap().to_string();
+                kv_request(args.address, KvRequest::DropNamespace { namespace });
+            }
+            if let ("list", Some(list_matches)) = namespace_matches.subcommand() {
+                cmd::print_address(list_matches);
+                let args = cmd::get_address(list_matches);
+                kv_request(args.address, KvRequest::ListNamespaces { consistency: cmd::get_read_consistency(list_matches) });
+            }
+            if let ("snapshot", Some(snapshot_matches)) = namespace_matches.subcommand() {
+                cmd::print_address(snapshot_matches);
+                let args = cmd::get_address(snapshot_matches);
+                let namespace = snapshot_matches.value_of("name").unwrap().to_string();
+                let path = snapshot_matches.value_of("file").unwrap().to_string();
+                snapshot_namespace_request(args.address, namespace, path);
+            }
+            if let ("restore", Some(restore_matches)) = namespace_matches.subcommand() {
+                cmd::print_address(restore_matches);
+                let args = cmd::get_address(restore_matches);
+                let namespace = restore_matches.value_of("name").unwrap().to_string();
+                let path = restore_matches.value_of("file").unwrap().to_string();
+                restore_namespace_request(args.address, namespace, path);
+            }
+        }
+        if let ("register-session", Some(session_matches)) = client_matches.subcommand() {
+            cmd::print_address(session_matches);
+            let args = cmd::get_address(session_matches);
+            kv_request(args.address, KvRequest::RegisterSession);
+        }
+
+        //TRANSFER_LEADER message
+        if let ("transfer-leader", Some(transfer_matches)) = client_matches.subcommand() {
+            cmd::print_address(transfer_matches);
+            let args = cmd::get_address(transfer_matches);
+            transfer_leader_request(args.address, cmd::get_node(transfer_matches));
+        }
     }
 }
\ No newline at end of file
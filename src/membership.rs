@@ -0,0 +1,430 @@
+//module for Raft cluster membership changes using joint consensus
+
+use std::net::SocketAddr;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use serde::{Serialize, Deserialize};
+use crate::kv_store::{get_meta, set_meta, MetaKey};
+use crate::server::{RaftClusterPeers, get_node_addr};
+use crate::log::{append_command, get_raft_log, last_log_index, LogCommand, LogEntry, TheLog};
+use crate::leader::{replicate_entry, sync_peer};
+
+//how far behind the leader's last log index a learner may be and still be promoted to a voter
+pub const MAX_PROMOTION_LAG: i32 = 5;
+
+//held while a membership change is proposed or finished, so only one thread ever appends C_new for a joint config
+static CONFIG_CHANGE_LOCK: Mutex<()> = Mutex::new(());
+
+/*
+    a cluster configuration; while next_voters is set the cluster is in the joint C_old,new configuration.
+    learners receive replication but never vote, and are not counted for commit quorum
+ */
+#[derive(Serialize, Deserialize, Debug, Clone, Hash, PartialEq)]
+pub struct ClusterConfig {
+    pub voters: Vec<SocketAddr>,
+    pub next_voters: Option<Vec<SocketAddr>>,
+    #[serde(default)]
+    pub learners: Vec<SocketAddr>,
+}
+
+//membership change requested by a client
+#[derive(Serialize, Deserialize, Debug, Clone, Hash, PartialEq)]
+pub enum ConfigChange {
+    AddNode(SocketAddr),
+    RemoveNode(SocketAddr),
+    AddLearner(SocketAddr),
+    PromoteLearner(SocketAddr),
+}
+
+impl ClusterConfig {
+    pub fn new(voters: Vec<SocketAddr>) -> ClusterConfig {
+        ClusterConfig {
+            voters,
+            next_voters: None,
+            learners: Vec::new(),
+        }
+    }
+
+    pub fn is_joint(&self) -> bool {
+        self.next_voters.is_some()
+    }
+
+    //every address in either the old or the new configuration, plus learners
+    pub fn members(&self) -> Vec<SocketAddr> {
+        let mut members = self.voters.clone();
+        let next_voters = self.next_voters.clone().unwrap_or_default();
+        for address in next_voters.iter().chain(self.learners.iter()) {
+            if !members.contains(address) {
+                members.push(*address);
+            }
+        }
+        members
+    }
+
+    //whether the address may vote (in either half of a joint configuration)
+    pub fn is_voter(&self, address: &SocketAddr) -> bool {
+        self.voters.contains(address) || self.next_voters.iter().any(|next_voters| next_voters.contains(address))
+    }
+
+    //learner changes do not affect quorum, so they are applied directly without a joint configuration
+    pub fn change_learners(&self, change: &ConfigChange) -> Result<ClusterConfig, String> {
+        let mut config = self.clone();
+        match change {
+            ConfigChange::AddLearner(address) => {
+                if self.members().contains(address) {
+                    return Err(format!("{} is already a cluster member", address));
+                }
+                config.learners.push(*address);
+            }
+            ConfigChange::RemoveNode(address) if self.learners.contains(address) => {
+                config.learners.retain(|learner| learner != address);
+            }
+            _ => return Err(String::from("not a learner change")),
+        }
+        Ok(config)
+    }
+
+    /*
+        while in the joint configuration, agreement (for elections and entry commitment)
+        requires separate majorities from both the old and the new configurations
+     */
+    pub fn has_quorum(&self, acks: &[SocketAddr]) -> bool {
+        if !is_majority(&self.voters, acks) {
+            return false;
+        }
+        match &self.next_voters {
+            Some(next_voters) => is_majority(next_voters, acks),
+            None => true,
+        }
+    }
+
+    //highest log index stored on a quorum, given each member's match_index (the leader's own last index included)
+    pub fn quorum_match_index(&self, match_indexes: &HashMap<SocketAddr, i32>) -> i32 {
+        let mut candidates: Vec<i32> = match_indexes.values().cloned().collect();
+        candidates.sort_unstable();
+        for index in candidates.into_iter().rev() {
+            let acks: Vec<SocketAddr> = match_indexes.iter()
+                .filter(|(_, match_index)| **match_index >= index)
+                .map(|(address, _)| *address)
+                .collect();
+            if self.has_quorum(&acks) {
+                return index;
+            }
+        }
+        0
+    }
+
+    //build the joint C_old,new configuration for a single change
+    pub fn begin_change(&self, change: &ConfigChange) -> Result<ClusterConfig, String> {
+        if self.is_joint() {
+            return Err(String::from("membership change already in progress"));
+        }
+        let mut next_voters = self.voters.clone();
+        match change {
+            ConfigChange::PromoteLearner(address) => {
+                if !self.learners.contains(address) {
+                    return Err(format!("{} is not a learner", address));
+                }
+                next_voters.push(*address);
+            }
+            ConfigChange::AddLearner(_) => {
+                return Err(String::from("learners are added without a joint configuration"));
+            }
+            ConfigChange::AddNode(address) => {
+                if next_voters.contains(address) {
+                    return Err(format!("{} is already a voting member", address));
+                }
+                next_voters.push(*address);
+            }
+            ConfigChange::RemoveNode(address) => {
+                if !next_voters.contains(address) {
+                    return Err(format!("{} is not a voting member", address));
+                }
+                next_voters.retain(|voter| voter != address);
+                if next_voters.is_empty() {
+                    return Err(String::from("cannot remove the last voting member"));
+                }
+            }
+        }
+        Ok(ClusterConfig {
+            voters: self.voters.clone(),
+            next_voters: Some(next_voters),
+            learners: self.learners.clone(),
+        })
+    }
+
+    //leave the joint configuration once C_old,new is committed; promoted learners are now voters
+    pub fn finalize(&self) -> ClusterConfig {
+        match &self.next_voters {
+            Some(next_voters) => ClusterConfig {
+                voters: next_voters.clone(),
+                next_voters: None,
+                learners: self.learners.iter().filter(|learner| !next_voters.contains(learner)).cloned().collect(),
+            },
+            None => self.clone(),
+        }
+    }
+}
+
+fn is_majority(voters: &[SocketAddr], acks: &[SocketAddr]) -> bool {
+    let votes = voters.iter().filter(|voter| acks.contains(voter)).count();
+    votes > voters.len() / 2
+}
+
+/*
+    initialize the cluster configuration in the kv store. The voters only form the bootstrap configuration, stored
+    the first time the server starts; a restarted server goes on with the latest configuration in its log
+ */
+pub fn init_cluster_config(voters: Vec<SocketAddr>) {
+    if get_meta::<ClusterConfig>(MetaKey::BootstrapConfig).is_none() {
+        set_meta(MetaKey::BootstrapConfig, &ClusterConfig::new(voters));
+    }
+    apply_cluster_config(latest_cluster_config(&get_raft_log()));
+}
+
+//the configuration of the latest config entry in the log, or the bootstrap configuration if the log has none
+pub fn latest_cluster_config(raft_log: &TheLog) -> ClusterConfig {
+    config_in_log(raft_log).unwrap_or_else(|| get_meta(MetaKey::BootstrapConfig).unwrap())
+}
+
+fn config_in_log(raft_log: &TheLog) -> Option<ClusterConfig> {
+    raft_log.log_entries.iter().rev().find_map(|entry| match &entry.command {
+        Some(LogCommand::ConfigChange(config)) => Some(config.clone()),
+        _ => None,
+    })
+}
+
+//go back to the latest configuration still in the log, after a conflicting suffix holding config entries was dropped
+pub fn restore_cluster_config() {
+    let config = latest_cluster_config(&get_raft_log());
+    if config != get_cluster_config() {
+        apply_cluster_config(config);
+    }
+}
+
+//whether this server is a non-voting learner
+pub fn is_learner() -> bool {
+    get_cluster_config().learners.contains(&get_node_addr())
+}
+
+//getter for the active ClusterConfig
+pub fn get_cluster_config() -> ClusterConfig {
+    get_meta(MetaKey::ClusterConfig).unwrap()
+}
+
+/*
+    a server always uses the latest configuration in its log, whether or not it is committed,
+    so the config is applied (and the peer list rebuilt) as soon as the entry is appended
+ */
+pub fn apply_cluster_config(config: ClusterConfig) {
+    set_meta(MetaKey::ClusterConfig, &config);
+
+    let node_addr = get_node_addr();
+    let mut cluster_peers = RaftClusterPeers {
+        addresses: Default::default()
+    };
+    for address in config.members() {
+        if address != node_addr {
+            cluster_peers.addresses.push_back(address);
+        }
+    }
+    set_meta(MetaKey::Peers, &cluster_peers);
+    println!("Cluster Config Applied: {:#?}", config);
+}
+
+/*
+    leader handling of a membership change:
+    1) append and replicate the joint C_old,new entry
+    2) once C_old,new is committed, append and replicate C_new
+ */
+pub fn propose_config_change(change: ConfigChange) -> Result<ClusterConfig, String> {
+    let _change_guard = CONFIG_CHANGE_LOCK.lock().unwrap();
+    let node_addr = get_node_addr();
+    if change == ConfigChange::RemoveNode(node_addr) {
+        return Err(String::from("cannot remove the current leader; transfer leadership first"));
+    }
+
+    let config = get_cluster_config();
+    let is_learner_change = match &change {
+        ConfigChange::AddLearner(_) => true,
+        ConfigChange::RemoveNode(address) => config.learners.contains(address),
+        _ => false,
+    };
+    if is_learner_change {
+        let learner_config = config.change_learners(&change)?;
+        let entry = append_command(node_addr.to_string(), LogCommand::ConfigChange(learner_config.clone()));
+        apply_cluster_config(learner_config.clone());
+        replicate_config(&learner_config, entry);
+        return Ok(learner_config);
+    }
+
+    //a learner only counts toward quorum once it has caught up with the leader's log
+    if let ConfigChange::PromoteLearner(address) = &change {
+        let lag = last_log_index(&get_raft_log()) - sync_peer(address).match_index;
+        if lag > MAX_PROMOTION_LAG {
+            return Err(format!("learner {} is {} entries behind the leader", address, lag));
+        }
+    }
+
+    let joint = config.begin_change(&change)?;
+    let entry = append_command(node_addr.to_string(), LogCommand::ConfigChange(joint.clone()));
+    apply_cluster_config(joint.clone());
+
+    if !replicate_config(&joint, entry) {
+        return Err(String::from("joint configuration not yet committed by a quorum; leader will retry"));
+    }
+    Ok(finalize_config_change(&joint))
+}
+
+/*
+    called from the leader workload to finish a change whose joint entry was not committed on the first attempt.
+    A change that is still being proposed is left to the proposing thread
+ */
+pub fn retry_pending_config_change() {
+    let _change_guard = match CONFIG_CHANGE_LOCK.try_lock() {
+        Ok(guard) => guard,
+        Err(_) => return,
+    };
+    let joint = get_cluster_config();
+    if !joint.is_joint() {
+        return
+    }
+    let raft_log = get_raft_log();
+    let joint_entry = raft_log.log_entries.iter().rev().find(|entry| {
+        matches!(&entry.command, Some(LogCommand::ConfigChange(config)) if *config == joint)
+    });
+    if let Some(entry) = joint_entry {
+        if replicate_config(&joint, entry.clone()) {
+            finalize_config_change(&joint);
+        }
+    }
+}
+
+//append C_new for a committed joint config; the caller holds CONFIG_CHANGE_LOCK
+fn finalize_config_change(joint: &ClusterConfig) -> ClusterConfig {
+    let final_config = joint.finalize();
+    let entry = append_command(get_node_addr().to_string(), LogCommand::ConfigChange(final_config.clone()));
+    apply_cluster_config(final_config.clone());
+    replicate_config(&final_config, entry);
+    final_config
+}
+
+//replicate a config entry to every member, and check whether it was acknowledged by a quorum (including the leader)
+fn replicate_config(config: &ClusterConfig, entry: LogEntry) -> bool {
+    let node_addr = get_node_addr();
+    let peers: Vec<SocketAddr> = config.members().into_iter().filter(|member| *member != node_addr).collect();
+    let mut acks = replicate_entry(peers, entry);
+    acks.push(node_addr);
+    config.has_quorum(&acks)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn addr(port: u16) -> SocketAddr {
+        SocketAddr::from(([127, 0, 0, 1], port))
+    }
+
+    #[test]
+    fn test_simple_majority() {
+        let config = ClusterConfig::new(vec![addr(8001), addr(8002), addr(8003)]);
+        assert!(!config.has_quorum(&[addr(8001)]));
+        assert!(config.has_quorum(&[addr(8001), addr(8003)]));
+    }
+
+    #[test]
+    fn test_add_node_joint_config() {
+        let config = ClusterConfig::new(vec![addr(8001), addr(8002), addr(8003)]);
+        let joint = config.begin_change(&ConfigChange::AddNode(addr(8004))).unwrap();
+
+        assert!(joint.is_joint());
+        assert_eq!(joint.members().len(), 4);
+        assert_eq!(joint.finalize().voters.len(), 4);
+        assert!(!joint.finalize().is_joint());
+    }
+
+    #[test]
+    fn test_joint_quorum_requires_both_majorities() {
+        let config = ClusterConfig::new(vec![addr(8001), addr(8002), addr(8003)]);
+        let joint = config.begin_change(&ConfigChange::RemoveNode(addr(8003))).unwrap();
+
+        //majority of C_old but only half of C_new
+        assert!(!joint.has_quorum(&[addr(8001), addr(8003)]));
+        assert!(joint.has_quorum(&[addr(8001), addr(8002)]));
+    }
+
+    #[test]
+    fn test_reject_invalid_changes() {
+        let config = ClusterConfig::new(vec![addr(8001)]);
+        assert!(config.begin_change(&ConfigChange::AddNode(addr(8001))).is_err());
+        assert!(config.begin_change(&ConfigChange::RemoveNode(addr(8002))).is_err());
+        assert!(config.begin_change(&ConfigChange::RemoveNode(addr(8001))).is_err());
+
+        let joint = config.begin_change(&ConfigChange::AddNode(addr(8002))).unwrap();
+        assert!(joint.begin_change(&ConfigChange::AddNode(addr(8003))).is_err());
+    }
+
+    #[test]
+    fn test_quorum_match_index() {
+        let config = ClusterConfig::new(vec![addr(8001), addr(8002), addr(8003)]);
+        let mut match_indexes = HashMap::new();
+        match_indexes.insert(addr(8001), 7);
+        match_indexes.insert(addr(8002), 5);
+        match_indexes.insert(addr(8003), 2);
+        assert_eq!(config.quorum_match_index(&match_indexes), 5);
+
+        //a learner that is far ahead does not move the quorum index
+        let config = config.change_learners(&ConfigChange::AddLearner(addr(8004))).unwrap();
+        match_indexes.insert(addr(8002), 1);
+        match_indexes.insert(addr(8004), 7);
+        assert_eq!(config.quorum_match_index(&match_indexes), 2);
+    }
+
+    #[test]
+    fn test_learners_do_not_count_for_quorum() {
+        let config = ClusterConfig::new(vec![addr(8001), addr(8002), addr(8003)]);
+        let config = config.change_learners(&ConfigChange::AddLearner(addr(8004))).unwrap();
+
+        assert!(!config.is_joint());
+        assert!(config.members().contains(&addr(8004)));
+        assert!(!config.is_voter(&addr(8004)));
+        assert!(!config.has_quorum(&[addr(8001), addr(8004)]));
+    }
+
+    #[test]
+    fn test_promote_learner() {
+        let config = ClusterConfig::new(vec![addr(8001), addr(8002), addr(8003)]);
+        assert!(config.begin_change(&ConfigChange::PromoteLearner(addr(8004))).is_err());
+
+        let config = config.change_learners(&ConfigChange::AddLearner(addr(8004))).unwrap();
+        let joint = config.begin_change(&ConfigChange::PromoteLearner(addr(8004))).unwrap();
+        assert!(joint.is_voter(&addr(8004)));
+
+        let promoted = joint.finalize();
+        assert_eq!(promoted.voters.len(), 4);
+        assert!(promoted.learners.is_empty());
+    }
+
+    #[test]
+    fn test_config_in_log() {
+        let entry = |command: Option<LogCommand>| LogEntry {
+            leader_term: 1,
+            leader_id: String::from("test"),
+            prev_index: 0,
+            prev_term: 0,
+            leader_commit_index: 0,
+            command,
+        };
+        let mut raft_log = TheLog::default();
+        raft_log.log_entries.push_back(entry(None));
+        assert_eq!(config_in_log(&raft_log), None);
+
+        let config = ClusterConfig::new(vec![addr(8001), addr(8002), addr(8003)]);
+        let joint = config.begin_change(&ConfigChange::AddNode(addr(8004))).unwrap();
+        raft_log.log_entries.push_back(entry(Some(LogCommand::ConfigChange(config))));
+        raft_log.log_entries.push_back(entry(Some(LogCommand::ConfigChange(joint.clone()))));
+        raft_log.log_entries.push_back(entry(None));
+        assert_eq!(config_in_log(&raft_log), Some(joint));
+    }
+}
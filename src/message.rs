@@ -7,15 +7,26 @@ use std::hash::{Hash, Hasher};
 use std::collections::hash_map::DefaultHasher;
 use std::borrow::Borrow;
 use crate::log;
-use crate::kv_store::{kv_db_setup, set_key, get_key};
-use crate::log::{TheLog, validate_log_entry, LogEntry};
-use crate::connection_handler::get_message_queue;
+use crate::kv_store::{set_meta, update_meta, MetaKey};
+use crate::storage::with_log_storage;
+use crate::log::{TheLog, get_raft_log, validate_log_entry, LogEntry, last_log_index, last_log_term};
+use crate::membership::{ClusterConfig, ConfigChange};
+use crate::state_machine::Txn;
+use crate::mvcc::{KvData, ScanPage};
+use crate::watch::WatchEvent;
 
 #[derive(Serialize, Deserialize, Debug, Clone, Hash)]
 pub enum MessageType {
     HEALTHCHECK,
     APPEND_ENTRY,
     REQUEST_VOTE,
+    CONFIG_CHANGE,
+    TRANSFER_LEADER,
+    TIMEOUT_NOW,
+    PRE_VOTE,
+    HEARTBEAT,
+    KV_REQUEST,
+    WATCH,
 }
 
 //generic message
@@ -36,6 +47,8 @@ pub struct HealthcheckResponse {
 //struct for the APPEND_ENTRY request
 #[derive(Serialize, Deserialize, Debug, Clone, Hash)]
 pub struct AppendEntryRequest {
+    //current term of the leader sending the entries; each entry keeps the term it was created in
+    pub term: i32,
     pub log_entry: log::LogEntry,
     pub entries: log::TheLog,
 }
@@ -48,6 +61,45 @@ pub struct AppendEntryRequestMessage {
     pub payload: AppendEntryRequest,
 }
 
+#[derive(Serialize, Deserialize, Debug, Clone, Hash)]
+pub struct AppendEntryReplyPayload {
+    pub term: i32,
+    pub success: bool,
+    pub match_index: i32,
+    //on a rejection, where the follower's log conflicts with the leader's; see log::conflict_hint
+    #[serde(default)]
+    pub conflict_term: Option<i32>,
+    #[serde(default)]
+    pub conflict_index: Option<i32>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Hash)]
+pub struct AppendEntryReplyMessage {
+    pub src_id: u64,
+    pub src_addr: SocketAddr,
+    pub msg_type: MessageType,
+    pub payload: AppendEntryReplyPayload,
+}
+
+//empty APPEND_ENTRY sent by the leader to assert leadership and collect acknowledgements
+#[derive(Serialize, Deserialize, Debug, Clone, Hash)]
+pub struct HeartbeatPayload {
+    pub term: i32,
+    pub leader: SocketAddr,
+    #[serde(default)]
+    pub commit_index: i32,
+    #[serde(default)]
+    pub commit_term: i32,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Hash)]
+pub struct HeartbeatMessage {
+    pub src_id: u64,
+    pub src_addr: SocketAddr,
+    pub msg_type: MessageType,
+    pub payload: HeartbeatPayload,
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone, Hash)]
 pub struct RequestVoteMessage {
     pub src_id: u64,
@@ -60,7 +112,12 @@ pub struct RequestVoteMessage {
 pub struct RequestVotePayload {
     pub last_log_index: i32,
     pub last_log_term: i32,
-    pub term: i32
+    pub term: i32,
+    #[serde(default)]
+    pub candidate: Option<SocketAddr>,
+    //set for elections started by TIMEOUT_NOW, which may disrupt a leader that is still alive
+    #[serde(default)]
+    pub leader_transfer: bool,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, Hash)]
@@ -77,36 +134,276 @@ pub struct RequestVoteReplyMessage {
     pub payload: RequestVoteReplyPayload,
 }
 
+#[derive(Serialize, Deserialize, Debug, Clone, Hash)]
+pub struct ConfigChangeMessage {
+    pub src_id: u64,
+    pub src_addr: SocketAddr,
+    pub msg_type: MessageType,
+    pub payload: ConfigChange,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Hash)]
+pub struct ConfigChangeReply {
+    pub msg_type: MessageType,
+    pub success: bool,
+    pub reason: String,
+    pub config: ClusterConfig,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Hash)]
+pub struct TransferLeaderMessage {
+    pub src_id: u64,
+    pub src_addr: SocketAddr,
+    pub msg_type: MessageType,
+    pub payload: SocketAddr,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Hash)]
+pub struct TransferLeaderReply {
+    pub msg_type: MessageType,
+    pub success: bool,
+    pub reason: String,
+}
+
+//only the msg_type of an incoming message, used to dispatch it to a handler
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct MessageHeader {
+    pub msg_type: MessageType,
+}
+
+//how far a stale read may lag behind the leader's last known commit index
+#[derive(Serialize, Deserialize, Debug, Clone, Hash, PartialEq)]
+pub enum StalenessBound {
+    Entries(i32),
+    Millis(u64),
+}
+
+//read consistency requested by the client for a GET
+#[derive(Serialize, Deserialize, Debug, Clone, Hash, PartialEq)]
+pub enum ReadConsistency {
+    Linearizable,
+    Lease,
+    Stale(StalenessBound),
+}
+
+//client key/value operations; writes are served by the leader, stale reads by any server
+#[derive(Serialize, Deserialize, Debug, Clone, Hash)]
+pub enum KvRequest {
+    Get {
+        key: String,
+        //when unset, the leader uses its configured read mode
+        #[serde(default)]
+        consistency: Option<ReadConsistency>,
+        //read the key as of a past revision (log index)
+        #[serde(default)]
+        revision: Option<u64>,
+    },
+    //keys in [start, end) in order; a page ends at limit keys, and its next key starts the following page
+    Scan {
+        start: String,
+        #[serde(default)]
+        end: Option<String>,
+        #[serde(default)]
+        limit: Option<usize>,
+        #[serde(default)]
+        consistency: Option<ReadConsistency>,
+        #[serde(default)]
+        revision: Option<u64>,
+    },
+    //keys starting with prefix in order, paginated like Scan from an optional start key
+    List {
+        prefix: String,
+        #[serde(default)]
+        start: Option<String>,
+        #[serde(default)]
+        limit: Option<usize>,
+        #[serde(default)]
+        consistency: Option<ReadConsistency>,
+        #[serde(default)]
+        revision: Option<u64>,
+    },
+    Set {
+        key: String,
+        value: String,
+        #[serde(default)]
+        session: Option<SessionTag>,
+        //expire the key after ttl milliseconds, on a new key lease of its own
+        #[serde(default)]
+        ttl: Option<u64>,
+        //attach the key to an existing key lease
+        #[serde(default)]
+        lease: Option<u64>,
+    },
+    Delete {
+        key: String,
+        #[serde(default)]
+        session: Option<SessionTag>,
+    },
+    CompareAndSwap {
+        key: String,
+        expected: String,
+        value: String,
+        #[serde(default)]
+        session: Option<SessionTag>,
+    },
+    SetIfAbsent {
+        key: String,
+        value: String,
+        #[serde(default)]
+        session: Option<SessionTag>,
+    },
+    DeleteIfVersion {
+        key: String,
+        version: u64,
+        #[serde(default)]
+        session: Option<SessionTag>,
+    },
+    Txn {
+        txn: Txn,
+        #[serde(default)]
+        session: Option<SessionTag>,
+    },
+    RegisterSession,
+    GrantLease {
+        ttl: u64,
+    },
+    KeepAliveLease {
+        id: u64,
+    },
+    RevokeLease {
+        id: u64,
+    },
+    //drop history older than a revision; reads before it are no longer possible
+    Compact {
+        revision: u64,
+    },
+    AcquireLock {
+        name: String,
+        ttl: u64,
+    },
+    RenewLock {
+        name: String,
+        token: u64,
+    },
+    ReleaseLock {
+        name: String,
+        token: u64,
+    },
+    //run a key request against a namespace other than the default one
+    Namespaced {
+        namespace: String,
+        request: Box<KvRequest>,
+    },
+    CreateNamespace {
+        namespace: String,
+    },
+    //removes the namespace and every key in it
+    DropNamespace {
+        namespace: String,
+    },
+    ListNamespaces {
+        #[serde(default)]
+        consistency: Option<ReadConsistency>,
+    },
+    //the namespace's keyspace and history, as a NamespaceSnapshot in the reply value
+    SnapshotNamespace {
+        namespace: String,
+        #[serde(default)]
+        consistency: Option<ReadConsistency>,
+    },
+    //replaces the namespace's keyspace, creating the namespace if it does not exist
+    RestoreNamespace {
+        namespace: String,
+        kv_data: KvData,
+    },
+}
+
+//identifies a client request for exactly-once semantics; sequence increases with every new request
+#[derive(Serialize, Deserialize, Debug, Clone, Hash, PartialEq)]
+pub struct SessionTag {
+    pub client_id: u64,
+    pub sequence: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Hash)]
+pub struct KvRequestMessage {
+    pub src_id: u64,
+    pub src_addr: SocketAddr,
+    pub msg_type: MessageType,
+    pub payload: KvRequest,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Hash)]
+pub struct KvReply {
+    pub msg_type: MessageType,
+    pub success: bool,
+    pub value: Option<String>,
+    pub reason: String,
+    //version of the key after a write, or as read; on a failed condition, the current version
+    #[serde(default)]
+    pub version: Option<u64>,
+    #[serde(default)]
+    pub page: Option<ScanPage>,
+    #[serde(default)]
+    pub revision: Option<u64>,
+}
+
+//watch a key, or every key starting with it, for changes applied at or after start_index (0: from now on)
+#[derive(Serialize, Deserialize, Debug, Clone, Hash)]
+pub struct WatchRequest {
+    pub key: String,
+    #[serde(default)]
+    pub prefix: bool,
+    #[serde(default)]
+    pub start_index: i32,
+    //when unset, the default namespace
+    #[serde(default)]
+    pub namespace: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Hash)]
+pub struct WatchRequestMessage {
+    pub src_id: u64,
+    pub src_addr: SocketAddr,
+    pub msg_type: MessageType,
+    pub payload: WatchRequest,
+}
+
+//one line of a watch stream; a response without events reports progress up to the applied index
+#[derive(Serialize, Deserialize, Debug, Clone, Hash)]
+pub struct WatchResponse {
+    pub msg_type: MessageType,
+    pub events: Vec<WatchEvent>,
+    pub applied: i32,
+    #[serde(default)]
+    pub error: Option<String>,
+}
+
 //initialize the server's message queue
 pub fn init_message_queue() {
     let msg_queue: VecDeque<AppendEntryRequestMessage> = Default::default();
-    let serialized = serde_json::to_string(&msg_queue).unwrap();
-    set_key(String::from("msg_queue"), serialized);
+    write_new_message_queue(msg_queue);
 }
 
 //process next APPEND_ENTRY request message
 pub fn process_next_message() {
     //pop_front from message_queue
-    let mut deque = get_message_queue();
-    if deque.is_empty() {
-        println!("Message Queue is Empty...Continuing");
-        return
-    }
-    let next_message = deque.pop_front().unwrap();
-    println!("Message Popped off Queue, new Size: {}", deque.len());
-    let updated_msg_queue = serde_json::to_string(&deque).unwrap();
-    set_key("msg_queue".parse().unwrap(), updated_msg_queue);
+    let next_message = match pop_message() {
+        Some(next_message) => next_message,
+        None => {
+            println!("Message Queue is Empty...Continuing");
+            return
+        }
+    };
 
     let log_entry: LogEntry = next_message.payload.log_entry;
 
     //process log entry before appending to log
-    let mut raft_log: TheLog = serde_json::from_str(&get_key(String::from("raft_log"))).unwrap();
+    let raft_log: TheLog = get_raft_log();
 
     //if log is empty, add message
     if raft_log.log_entries.clone().is_empty() {
-        raft_log.log_entries.push_back(log_entry.clone());
-        let serialized = serde_json::to_string(&raft_log).unwrap();
-        set_key(String::from("raft_log"), serialized);
+        with_log_storage(|storage| storage.append(std::slice::from_ref(&log_entry)));
         println!("Log Appended: {:#?}", log_entry.clone());
         return
     }
@@ -122,25 +419,29 @@ pub fn process_next_message() {
 }
 
 pub fn write_new_message_queue(deque: VecDeque<AppendEntryRequestMessage>) {
-    let serialized = serde_json::to_string(&deque).unwrap();
-    set_key(String::from("msg_queue"), serialized);
+    set_meta(MetaKey::MsgQueue, &deque);
 }
 
-//generate a RequestVotePayload
-pub fn generate_request_vote_payload(mut raft_log: TheLog) -> RequestVotePayload {
-    if raft_log.log_entries.is_empty() {
-        return RequestVotePayload{
-            last_log_index: 0,
-            last_log_term: 0,
-            term: 1,
-        }
-    } else {
-        let last_log = raft_log.log_entries.pop_back().unwrap();
-        RequestVotePayload{
-            last_log_index: last_log.leader_commit_index,
-            last_log_term: last_log.leader_term,
-            term: last_log.leader_term + 1, //increment term
-        }
+pub fn push_message(message: AppendEntryRequestMessage) {
+    update_meta(MetaKey::MsgQueue, |deque: &mut VecDeque<AppendEntryRequestMessage>| deque.push_back(message));
+}
+
+fn pop_message() -> Option<AppendEntryRequestMessage> {
+    update_meta(MetaKey::MsgQueue, |deque: &mut VecDeque<AppendEntryRequestMessage>| {
+        let next_message = deque.pop_front();
+        println!("Message Queue Size: {}", deque.len());
+        next_message
+    })
+}
+
+//generate a RequestVotePayload for a candidate campaigning in the given term
+pub fn generate_request_vote_payload(raft_log: TheLog, term: i32, candidate: SocketAddr) -> RequestVotePayload {
+    RequestVotePayload{
+        last_log_index: last_log_index(&raft_log),
+        last_log_term: last_log_term(&raft_log),
+        term,
+        candidate: Some(candidate),
+        leader_transfer: false,
     }
 }
 
@@ -165,12 +466,14 @@ pub fn get_dummy_append_entry_req() -> AppendEntryRequest {
         prev_index: 0,
         prev_term: 0,
         leader_commit_index: 1,
+        command: None,
     };
 
     let mut log : VecDeque<log::LogEntry> = VecDeque::new();
     log.push_back(dummy_log_entry.clone());
 
     AppendEntryRequest{
+        term: 1,
         log_entry: dummy_log_entry,
         entries: log::TheLog{log_entries: log}
     }
@@ -181,6 +484,8 @@ pub fn get_dummy_request_vote() -> RequestVotePayload {
         last_log_index: 1,
         last_log_term: 1,
         term: 1,
+        candidate: None,
+        leader_transfer: false,
     }
 }
 
@@ -0,0 +1,186 @@
+//module for the multi-version client keyspace: every key keeps its revisions, tagged with the log index that wrote them
+
+use std::collections::BTreeMap;
+use std::ops::Bound;
+use serde::{Serialize, Deserialize};
+
+/*
+    a key's value with its version: the log index of the entry that last wrote the key. Versions only grow, and
+    a deleted and re-created key never gets an old version back, so a stale version can not match by accident
+ */
+#[derive(Serialize, Deserialize, Debug, Clone, Hash, PartialEq)]
+pub struct VersionedValue {
+    pub value: String,
+    pub version: u64,
+}
+
+//one revision of a key: the value written at a log index, or None where the key was deleted
+#[derive(Serialize, Deserialize, Debug, Clone, Hash, PartialEq)]
+pub struct KeyRevision {
+    pub index: u64,
+    pub value: Option<String>,
+}
+
+/*
+    the client keyspace, ordered by key so range and prefix scans walk keys in order, with the revisions of each
+    key in log order; it is stored and restored as a whole, and serializes in key order
+ */
+pub type KvData = BTreeMap<String, Vec<KeyRevision>>;
+
+//one key of a scan result
+#[derive(Serialize, Deserialize, Debug, Clone, Hash, PartialEq)]
+pub struct ScanEntry {
+    pub key: String,
+    pub value: String,
+    pub version: u64,
+}
+
+//a page of scan results in key order; when set, next is the start key of the following page
+#[derive(Serialize, Deserialize, Debug, Clone, Hash, PartialEq, Default)]
+pub struct ScanPage {
+    pub entries: Vec<ScanEntry>,
+    pub next: Option<String>,
+}
+
+//the key's value as of a revision (a log index), or its latest value
+pub fn value_at(revisions: &[KeyRevision], revision: Option<u64>) -> Option<VersionedValue> {
+    let visible = revisions.iter().rev()
+        .find(|key_revision| revision.is_none_or(|revision| key_revision.index <= revision))?;
+    visible.value.as_ref().map(|value| VersionedValue { value: value.clone(), version: visible.index })
+}
+
+//record a write (or, with no value, a delete) of a key at a log index
+pub fn put_revision(kv_data: &mut KvData, key: String, index: u64, value: Option<String>) {
+    kv_data.entry(key).or_default().push(KeyRevision { index, value });
+}
+
+//keys in [start, end) as of a revision, in order, at most limit of them; next is where the following page starts
+pub fn scan_range(kv_data: &KvData, start: &str, end: Option<&str>, limit: usize, revision: Option<u64>) -> ScanPage {
+    let mut range = kv_data.range::<str, _>((Bound::Included(start), Bound::Unbounded))
+        .take_while(|(key, _)| end.is_none_or(|end| key.as_str() < end))
+        .filter_map(|(key, revisions)| value_at(revisions, revision).map(|versioned| (key, versioned)));
+    let entries: Vec<ScanEntry> = range.by_ref().take(limit).map(|(key, versioned)| ScanEntry {
+        key: key.clone(),
+        value: versioned.value,
+        version: versioned.version,
+    }).collect();
+    ScanPage {
+        entries,
+        next: range.next().map(|(key, _)| key.clone()),
+    }
+}
+
+//keys starting with prefix, from start (or the prefix itself) onwards
+pub fn scan_prefix(kv_data: &KvData, prefix: &str, start: Option<&str>, limit: usize, revision: Option<u64>) -> ScanPage {
+    let start = start.filter(|start| *start > prefix).unwrap_or(prefix);
+    let mut page = scan_range(kv_data, start, None, limit + 1, revision);
+    page.entries.retain(|entry| entry.key.starts_with(prefix));
+    page.next = if page.entries.len() > limit { page.entries.pop().map(|entry| entry.key) } else { None };
+    page
+}
+
+/*
+    compaction drops the history that no read at or after the revision can see: each key only keeps the revision
+    visible at it and any later ones, and a key deleted by then keeps nothing before its next write
+ */
+pub fn compact(kv_data: &mut KvData, revision: u64) {
+    for revisions in kv_data.values_mut() {
+        if let Some(visible) = revisions.iter().rposition(|key_revision| key_revision.index <= revision) {
+            revisions.drain(..visible);
+            if revisions[0].value.is_none() {
+                revisions.remove(0);
+            }
+        }
+    }
+    kv_data.retain(|_, revisions| !revisions.is_empty());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn kv_data(keys: &[&str]) -> KvData {
+        let mut kv_data = KvData::new();
+        for (index, key) in keys.iter().enumerate() {
+            put_revision(&mut kv_data, key.to_string(), index as u64 + 1, Some(key.to_uppercase()));
+        }
+        kv_data
+    }
+
+    fn scanned_keys(page: &ScanPage) -> Vec<&str> {
+        page.entries.iter().map(|entry| entry.key.as_str()).collect()
+    }
+
+    #[test]
+    fn test_scan_range_pages() {
+        let kv_data = kv_data(&["e", "a", "c", "b", "d"]);
+
+        let page = scan_range(&kv_data, "a", Some("e"), 2, None);
+        assert_eq!(scanned_keys(&page), vec!["a", "b"]);
+        assert_eq!(page.next, Some(String::from("c")));
+
+        let page = scan_range(&kv_data, &page.next.unwrap(), Some("e"), 2, None);
+        assert_eq!(scanned_keys(&page), vec!["c", "d"]);
+        //the end key is excluded
+        assert_eq!(page.next, None);
+
+        assert_eq!(scanned_keys(&scan_range(&kv_data, "bb", None, 10, None)), vec!["c", "d", "e"]);
+    }
+
+    #[test]
+    fn test_scan_prefix_pages() {
+        let kv_data = kv_data(&["app/a", "app/b", "app/c", "apple", "b"]);
+
+        let page = scan_prefix(&kv_data, "app/", None, 2, None);
+        assert_eq!(scanned_keys(&page), vec!["app/a", "app/b"]);
+        assert_eq!(page.next, Some(String::from("app/c")));
+
+        let page = scan_prefix(&kv_data, "app/", page.next.as_deref(), 2, None);
+        assert_eq!(scanned_keys(&page), vec!["app/c"]);
+        assert_eq!(page.next, None);
+    }
+
+    #[test]
+    fn test_kv_data_serializes_in_key_order() {
+        let serialized = serde_json::to_string(&kv_data(&["c", "a", "b"])).unwrap();
+        let restored: KvData = serde_json::from_str(&serialized).unwrap();
+        assert!(serialized.find("\"a\"").unwrap() < serialized.find("\"b\"").unwrap());
+        assert_eq!(restored.keys().collect::<Vec<_>>(), vec!["a", "b", "c"]);
+    }
+
+    #[test]
+    fn test_reads_at_past_revisions() {
+        let mut kv_data = KvData::new();
+        put_revision(&mut kv_data, String::from("a"), 2, Some(String::from("one")));
+        put_revision(&mut kv_data, String::from("b"), 3, Some(String::from("x")));
+        put_revision(&mut kv_data, String::from("a"), 5, Some(String::from("two")));
+        put_revision(&mut kv_data, String::from("b"), 6, None);
+
+        let value = |revision| value_at(&kv_data["a"], revision).map(|versioned| versioned.value);
+        assert_eq!(value(Some(1)), None);
+        assert_eq!(value(Some(4)), Some(String::from("one")));
+        assert_eq!(value(None), Some(String::from("two")));
+
+        assert_eq!(scanned_keys(&scan_range(&kv_data, "", None, 10, Some(4))), vec!["a", "b"]);
+        //b was deleted at 6
+        assert_eq!(scanned_keys(&scan_range(&kv_data, "", None, 10, None)), vec!["a"]);
+    }
+
+    #[test]
+    fn test_compact() {
+        let mut kv_data = KvData::new();
+        put_revision(&mut kv_data, String::from("a"), 2, Some(String::from("one")));
+        put_revision(&mut kv_data, String::from("a"), 5, Some(String::from("two")));
+        put_revision(&mut kv_data, String::from("a"), 8, Some(String::from("three")));
+        put_revision(&mut kv_data, String::from("b"), 3, Some(String::from("x")));
+        put_revision(&mut kv_data, String::from("b"), 4, None);
+
+        compact(&mut kv_data, 6);
+
+        let indexes: Vec<u64> = kv_data["a"].iter().map(|key_revision| key_revision.index).collect();
+        assert_eq!(indexes, vec![5, 8]);
+        //reads at or after the compacted revision are unchanged
+        assert_eq!(value_at(&kv_data["a"], Some(6)).unwrap().value, "two");
+        assert!(!kv_data.contains_key("b"));
+    }
+}
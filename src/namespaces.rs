@@ -0,0 +1,116 @@
+//module for namespaces: isolated client keyspaces inside the state machine, each stored on its own
+
+use std::collections::BTreeSet;
+use std::fs;
+use serde::{Serialize, Deserialize};
+use crate::kv_store::{get_key, set_key};
+use crate::message::KvRequest;
+use crate::leader::send_kv_request;
+use crate::mvcc::KvData;
+use crate::log::LogCommand;
+
+//the namespace of requests that do not name one; it always exists and can not be dropped
+pub const DEFAULT_NAMESPACE: &str = "default";
+
+//a copy of one namespace's keyspace, with its full history, as of a revision
+#[derive(Serialize, Deserialize, Debug, Clone, Hash, PartialEq)]
+pub struct NamespaceSnapshot {
+    pub namespace: String,
+    pub revision: u64,
+    pub kv_data: KvData,
+}
+
+//kv store key holding a namespace's keyspace; the default namespace keeps the original location
+pub fn kv_data_key(namespace: &str) -> String {
+    if namespace == DEFAULT_NAMESPACE {
+        String::from("kv_data")
+    } else {
+        format!("kv_data/{}", namespace)
+    }
+}
+
+//every namespace, including the default one
+pub fn get_namespaces() -> BTreeSet<String> {
+    let mut namespaces: BTreeSet<String> = serde_json::from_str(&get_key(String::from("namespaces"))).unwrap_or_default();
+    namespaces.insert(String::from(DEFAULT_NAMESPACE));
+    namespaces
+}
+
+pub fn set_namespaces(namespaces: &BTreeSet<String>) {
+    let serialized = serde_json::to_string(namespaces).unwrap();
+    set_key(String::from("namespaces"), serialized);
+}
+
+pub fn namespace_exists(namespace: &str) -> bool {
+    get_namespaces().contains(namespace)
+}
+
+//key commands outside the default namespace are logged wrapped with their namespace
+pub fn in_namespace(namespace: &str, command: LogCommand) -> LogCommand {
+    if namespace == DEFAULT_NAMESPACE {
+        command
+    } else {
+        LogCommand::Namespaced { namespace: namespace.to_string(), command: Box::new(command) }
+    }
+}
+
+//namespace names end up in storage keys, so they are kept to letters, digits, '-' and '_'
+pub fn is_valid_namespace_name(namespace: &str) -> bool {
+    !namespace.is_empty() && namespace.len() <= 64
+        && namespace.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+//client side of namespace snapshot: writes the namespace's keyspace to a file
+pub fn snapshot_namespace_request(dest_addr: String, namespace: String, path: String) {
+    let reply = match send_kv_request(&dest_addr, KvRequest::SnapshotNamespace { namespace, consistency: None }) {
+        Ok(reply) => reply,
+        Err(e) => {
+            println!("Failed to take snapshot: {}", e);
+            return;
+        }
+    };
+    match reply.value {
+        Some(snapshot) if reply.success => {
+            fs::write(&path, snapshot).unwrap();
+            println!("Snapshot at revision {:?} written to {}", reply.revision, path);
+        }
+        _ => println!("Failed to take snapshot: {}", reply.reason),
+    }
+}
+
+//client side of namespace restore: replaces the namespace's keyspace with a snapshot file, creating it if needed
+pub fn restore_namespace_request(dest_addr: String, namespace: String, path: String) {
+    let snapshot: NamespaceSnapshot = serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+    match send_kv_request(&dest_addr, KvRequest::RestoreNamespace { namespace, kv_data: snapshot.kv_data }) {
+        Ok(reply) => println!("{:#?}", reply),
+        Err(e) => println!("Failed to restore snapshot: {}", e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_namespace_names() {
+        assert!(is_valid_namespace_name("team-a_2"));
+        assert!(!is_valid_namespace_name(""));
+        assert!(!is_valid_namespace_name("a/b"));
+        assert!(!is_valid_namespace_name(&"a".repeat(65)));
+        assert_eq!(kv_data_key(DEFAULT_NAMESPACE), "kv_data");
+        assert_eq!(kv_data_key("team-a"), "kv_data/team-a");
+    }
+
+    #[test]
+    fn test_in_namespace() {
+        let delete = || LogCommand::Delete { key: String::from("k") };
+        assert!(matches!(in_namespace(DEFAULT_NAMESPACE, delete()), LogCommand::Delete { .. }));
+        match in_namespace("team-a", delete()) {
+            LogCommand::Namespaced { namespace, command } => {
+                assert_eq!(namespace, "team-a");
+                assert!(matches!(*command, LogCommand::Delete { .. }));
+            }
+            _ => panic!("expected a namespaced command"),
+        }
+    }
+}
@@ -0,0 +1,185 @@
+//module for serving client reads from the key/value state machine at a requested consistency
+
+use std::{thread, time};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Mutex;
+use crate::message::{StalenessBound, ReadConsistency};
+use crate::leader::{confirm_leadership, has_valid_lease, is_leader, has_committed_in_term};
+use crate::election::ELECTION_TIMEOUT;
+use crate::state_machine::{get_commit_index, get_last_applied, apply_committed_entries};
+
+//how long a read waits for the state machine to catch up to its read index
+const READ_INDEX_TIMEOUT: u64 = 2000;
+
+//default allowance (in ms) for clock drift between servers when computing the leader lease
+pub const DEFAULT_CLOCK_DRIFT_MARGIN: u64 = 500;
+
+//whether the leader serves reads from its lease instead of a ReadIndex heartbeat round
+static LEASE_READ: AtomicBool = AtomicBool::new(false);
+
+static CLOCK_DRIFT_MARGIN: AtomicU64 = AtomicU64::new(DEFAULT_CLOCK_DRIFT_MARGIN);
+
+//the leader's commit index from its last heartbeat, and when it was received
+static LEADER_COMMIT: Mutex<Option<(i32, time::Instant)>> = Mutex::new(None);
+
+pub fn set_lease_read(enabled: bool, clock_drift_margin: u64) {
+    LEASE_READ.store(enabled, Ordering::SeqCst);
+    CLOCK_DRIFT_MARGIN.store(clock_drift_margin, Ordering::SeqCst);
+}
+
+pub fn get_clock_drift_margin() -> u64 {
+    CLOCK_DRIFT_MARGIN.load(Ordering::SeqCst)
+}
+
+//a lease lasts one election timeout less the clock drift margin
+pub fn lease_duration(clock_drift_margin: u64) -> time::Duration {
+    time::Duration::from_millis(ELECTION_TIMEOUT.saturating_sub(clock_drift_margin))
+}
+
+//serve a read at the consistency requested by the client; only stale reads are served by followers
+pub fn consistent_read<T, F>(consistency: Option<ReadConsistency>, read: F) -> Result<T, String>
+    where F: FnOnce() -> Result<T, String> {
+    match consistency {
+        Some(ReadConsistency::Stale(bound)) => stale_read(read, &bound, is_leader()),
+        _ if !is_leader() => Err(String::from("not the leader")),
+        Some(ReadConsistency::Linearizable) => linearizable_read(read),
+        Some(ReadConsistency::Lease) => lease_read(read),
+        None => leader_read(read),
+    }
+}
+
+//reads on the leader use the lease when lease read mode is enabled, otherwise ReadIndex
+pub fn leader_read<T, F>(read: F) -> Result<T, String> where F: FnOnce() -> Result<T, String> {
+    if LEASE_READ.load(Ordering::SeqCst) {
+        lease_read(read)
+    } else {
+        linearizable_read(read)
+    }
+}
+
+/*
+    lease read: while the lease from the last quorum heartbeat is held, no other leader can have been
+    elected, so the read is served locally; once the lease has expired the read falls back to ReadIndex
+ */
+pub fn lease_read<T, F>(read: F) -> Result<T, String> where F: FnOnce() -> Result<T, String> {
+    if !has_valid_lease() {
+        return linearizable_read(read);
+    }
+    wait_for_term_commit()?;
+    wait_for_applied(get_commit_index())?;
+    read()
+}
+
+/*
+    ReadIndex: a linearizable read served by the leader without appending anything to the log
+    0) wait for the NoOp from the start of the leader's term to commit; until then the leader's commit index
+       may be behind entries committed by an earlier leader
+    1) record the current commit index as the read index
+    2) confirm leadership with a heartbeat round acknowledged by a quorum
+    3) wait for last_applied to reach the read index
+    4) serve the read from the local state machine
+ */
+pub fn linearizable_read<T, F>(read: F) -> Result<T, String> where F: FnOnce() -> Result<T, String> {
+    wait_for_term_commit()?;
+    let read_index = get_commit_index();
+
+    if !confirm_leadership() {
+        return Err(String::from("leadership could not be confirmed by a quorum"));
+    }
+
+    wait_for_applied(read_index)?;
+    read()
+}
+
+pub fn record_leader_commit(leader_commit: i32) {
+    *LEADER_COMMIT.lock().unwrap() = Some((leader_commit, time::Instant::now()));
+}
+
+/*
+    stale read: served by any server from its local state machine, as long as last_applied is within the
+    requested number of entries of the leader's last known commit index, or has caught up to it and that
+    commit index was heard within the requested number of milliseconds
+ */
+pub fn stale_read<T, F>(read: F, bound: &StalenessBound, is_leader: bool) -> Result<T, String>
+    where F: FnOnce() -> Result<T, String> {
+    apply_committed_entries();
+    if is_leader {
+        return read();
+    }
+
+    let leader_commit = *LEADER_COMMIT.lock().unwrap();
+    match leader_commit {
+        Some((commit_index, received)) => {
+            if within_staleness_bound(bound, commit_index, get_last_applied(), received.elapsed()) {
+                read()
+            } else {
+                Err(format!("stale read rejected: last_applied {} is outside {:?} of leader commit index {}",
+                            get_last_applied(), bound, commit_index))
+            }
+        }
+        None => Err(String::from("stale read rejected: no commit index heard from a leader yet")),
+    }
+}
+
+fn within_staleness_bound(bound: &StalenessBound, leader_commit: i32, last_applied: i32,
+                          since_heard: time::Duration) -> bool {
+    match bound {
+        StalenessBound::Entries(max_lag) => leader_commit - last_applied <= *max_lag,
+        StalenessBound::Millis(max_lag) => {
+            last_applied >= leader_commit && since_heard <= time::Duration::from_millis(*max_lag)
+        }
+    }
+}
+
+fn wait_for_term_commit() -> Result<(), String> {
+    let deadline = time::Instant::now() + time::Duration::from_millis(READ_INDEX_TIMEOUT);
+    while !has_committed_in_term() {
+        if time::Instant::now() >= deadline {
+            return Err(String::from("timed out waiting for the leader to commit an entry from its term"));
+        }
+        thread::sleep(time::Duration::from_millis(10));
+    }
+    Ok(())
+}
+
+fn wait_for_applied(read_index: i32) -> Result<(), String> {
+    let deadline = time::Instant::now() + time::Duration::from_millis(READ_INDEX_TIMEOUT);
+    loop {
+        apply_committed_entries();
+        if get_last_applied() >= read_index {
+            return Ok(());
+        }
+        if time::Instant::now() >= deadline {
+            return Err(format!("timed out waiting for read index {} to be applied", read_index));
+        }
+        thread::sleep(time::Duration::from_millis(10));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lease_duration() {
+        assert_eq!(lease_duration(500), time::Duration::from_millis(ELECTION_TIMEOUT - 500));
+        assert_eq!(lease_duration(0), time::Duration::from_millis(ELECTION_TIMEOUT));
+        //a margin larger than the election timeout means no lease at all
+        assert_eq!(lease_duration(ELECTION_TIMEOUT + 1), time::Duration::from_millis(0));
+    }
+
+    #[test]
+    fn test_staleness_bound_entries() {
+        let since_heard = time::Duration::from_millis(0);
+        assert!(within_staleness_bound(&StalenessBound::Entries(3), 10, 7, since_heard));
+        assert!(!within_staleness_bound(&StalenessBound::Entries(3), 10, 6, since_heard));
+    }
+
+    #[test]
+    fn test_staleness_bound_millis() {
+        let bound = StalenessBound::Millis(500);
+        assert!(within_staleness_bound(&bound, 10, 10, time::Duration::from_millis(200)));
+        assert!(!within_staleness_bound(&bound, 10, 10, time::Duration::from_millis(800)));
+        assert!(!within_staleness_bound(&bound, 10, 9, time::Duration::from_millis(200)));
+    }
+}
@@ -1,13 +1,20 @@
 use std::net::{SocketAddr, TcpListener, TcpStream};
-use crate::leader::{IS_LEADER, do_leader_workload};
-use crate::message::{init_message_queue, RequestVotePayload, RequestVoteMessage, calculate_hash, MessageType, generate_request_vote_payload};
-use crate::log::{initialize_raft_log, TheLog, get_raft_log};
+use crate::leader::{IS_LEADER, start_leader_term, set_leader};
+use crate::driver::start_driver;
+use crate::message::{init_message_queue, RequestVotePayload, RequestVoteMessage, RequestVoteReplyMessage, RequestVoteReplyPayload, calculate_hash, MessageType, generate_request_vote_payload};
+use crate::log::{load_raft_log, load_hard_state, TheLog, get_raft_log, initialize_hard_state, get_hard_state, set_hard_state, HardState};
+use crate::election::{set_pre_vote, is_pre_vote_enabled};
+use crate::reads::set_lease_read;
+use crate::state_machine::restore_applied_index;
+use crate::storage::{set_log_storage, MemoryLogStorage};
 use std::collections::VecDeque;
-use crate::kv_store::{set_key, get_key};
-use std::{thread, time};
-use crate::connection_handler::{connection_handler, serialize_request_vote, handle_resp};
+use crate::kv_store::{get_meta, set_meta, MetaKey};
+use std::thread;
+use crate::connection_handler::{connection_handler, serialize_request_vote, handle_resp, read_resp};
+use crate::membership::{init_cluster_config, get_cluster_config};
 use serde::{Serialize, Deserialize};
 use std::io::Write;
+use std::sync::atomic::AtomicBool;
 
 struct Addrs {
     addresses: [SocketAddr; 5],
@@ -19,32 +26,33 @@ pub struct RaftClusterPeers {
 }
 
 fn set_raft_cluster_peers(peers: Addrs, tcp_listener: TcpListener) {
-    //get RaftClusterPeers
-    let mut cluster_peers = RaftClusterPeers{
-        addresses: VecDeque::new()
-    };
-    for address in peers.addresses.iter() {
+    set_meta(MetaKey::NodeAddr, &tcp_listener.local_addr().unwrap());
 
-        if address.port() != tcp_listener.local_addr().unwrap().port() {
-            cluster_peers.addresses.push_back(*address)
-        }
-    }
+    //every address in the pool starts off as a voting member; RaftClusterPeers is derived from the config
+    //a restart keeps the membership recorded in the log instead
+    init_cluster_config(peers.addresses.to_vec());
 
-    let serialized_peers = serde_json::to_string(&cluster_peers).unwrap();
-    set_key("peers".parse().unwrap(), serialized_peers);
     //print out followers
-    let peers: RaftClusterPeers = serde_json::from_str(&get_key(String::from("peers"))).unwrap();
+    let peers = get_raft_peers();
     println!("{:#?}", peers);
 }
 
 //getter for RaftClusterPeers
 fn get_raft_peers() -> RaftClusterPeers {
-    serde_json::from_str(&get_key(String::from("peers"))).unwrap()
+    get_meta(MetaKey::Peers).unwrap()
+}
+
+//getter for this server's own listener address
+pub fn get_node_addr() -> SocketAddr {
+    get_meta(MetaKey::NodeAddr).unwrap()
 }
 
-pub fn setup_tcp_listener(is_leader: bool) {
+pub fn setup_tcp_listener(is_leader: bool, bind_address: Option<SocketAddr>, pre_vote: bool,
+                          lease_read: bool, clock_drift_margin: u64, in_memory_log: bool) {
 
-    IS_LEADER.set(is_leader);
+    IS_LEADER.set(AtomicBool::new(is_leader));
+    set_pre_vote(pre_vote);
+    set_lease_read(lease_read, clock_drift_margin);
 
     //available address pool for our Raft Server(s)
     let addrs = [
@@ -57,14 +65,28 @@ pub fn setup_tcp_listener(is_leader: bool) {
 
     let bind_addresses = Addrs{addresses: addrs};
 
-    //bind TcpListener to first available address/port from ADDRS
-    let tcp_listener = TcpListener::bind(&bind_addresses.addresses[..]).unwrap();
+    //bind TcpListener to the requested address (a node joining a running cluster),
+    //otherwise to first available address/port from ADDRS
+    let tcp_listener = match bind_address {
+        Some(address) => TcpListener::bind(address).unwrap(),
+        None => TcpListener::bind(&bind_addresses.addresses[..]).unwrap(),
+    };
 
     //initialize server's message queue
     init_message_queue();
 
-    //initialize raft_log
-    initialize_raft_log();
+    //the log and hard state go to the Raft metadata file unless asked to stay in memory
+    if in_memory_log {
+        set_log_storage(Box::new(MemoryLogStorage::default()));
+    }
+
+    //pick up the log and the state machine where they were before a restart
+    println!("Loaded a log of {} entries", load_raft_log());
+    restore_applied_index();
+
+    //a fresh server starts off in term 0, and one started as leader in term 1
+    let hard_state = load_hard_state(is_leader.then(|| tcp_listener.local_addr().unwrap()));
+    println!("Starting in term {}", hard_state.current_term);
 
     //print out connection info
     println!("TCP Listener on address: {:#?}, port: {:#?}",
@@ -74,12 +96,10 @@ pub fn setup_tcp_listener(is_leader: bool) {
     //set cluster peer addresses in kv store
     set_raft_cluster_peers(bind_addresses, tcp_listener.try_clone().unwrap());
 
-    //do leader workload
-    if *IS_LEADER.get::<bool>() {
-        do_leader_workload();
-    } else {
-        //start follower election timer
-        init_election_timer();
+    //the driver runs the election and heartbeat timers, so the listener below accepts connections on every server
+    start_driver();
+    if is_leader {
+        start_leader_term();
     }
 
     //using incoming() which calls the accept() fn for each connection
@@ -106,51 +126,103 @@ pub fn setup_tcp_listener(is_leader: bool) {
     drop(tcp_listener);
 }
 
-fn init_election_timer() {
-    let handler = thread::spawn(move|| {
-        let mut i = 10;
-        let timer_end = 0;
-        let step = 1;
-        println!("Initializing ELECTION_TIMER...");
-        while i > timer_end {
-            println!("Counting Down Election Timer...");
+/*
+    run an election for the next term; with PRE_VOTE enabled, the term is only incremented
+    if a quorum would grant a vote, so a partitioned node cannot inflate its term
+ */
+pub fn broadcast_request_vote() {
+    run_election(is_pre_vote_enabled(), false);
+}
 
-            i -= step;
+//TIMEOUT_NOW elections skip PRE_VOTE, and are flagged so voters that recently heard from the leader still vote
+pub fn run_election(pre_vote: bool, leader_transfer: bool) {
+    let node_addr = get_node_addr();
+    let proposed_term = get_hard_state().current_term + 1;
 
-            let sleep_time = time::Duration::from_millis(1000);
-            time::Instant::now();
-            thread::sleep(sleep_time);
-        }
+    if pre_vote && !collect_votes(MessageType::PRE_VOTE, proposed_term, leader_transfer) {
+        println!("PRE_VOTE for term {} did not reach a quorum; remaining a follower", proposed_term);
+        return
+    }
 
-        //trigger RequestVote message
-        println!("ELECTION_TIMER countdown completed; Server in CANDIDATE_STATE, Triggering Election");
-        //TODO send RequestVoteMessage
-        broadcast_request_vote();
+    //become a candidate: increment the term and vote for self
+    set_hard_state(HardState {
+        current_term: proposed_term,
+        voted_for: Some(node_addr),
     });
 
-    //join thread handling election timer
-    match handler.join() {
-        Ok(_) => {
-            //restart the election timer
-            init_election_timer();
-        }
-        Err(e) => {println!("Error Joining Election Timer Thread: {:#?}", e)}
+    if collect_votes(MessageType::REQUEST_VOTE, proposed_term, leader_transfer) {
+        println!("Election won for term {}", proposed_term);
+        set_leader(true);
+        start_leader_term();
     }
 }
 
-fn broadcast_request_vote() {
-    //get the raft_log
-    let raft_peers = get_raft_peers();
-    for peer in raft_peers.addresses {
-        //println!("{:#?}", peer.to_string());
-        let handler = thread::spawn(move|| {
-            let mut raft_log: TheLog = get_raft_log();
-            let request_vote_payload = generate_request_vote_payload(raft_log);
-            request_vote(peer.to_string(), request_vote_payload);
-        });
-        handler.join().expect("Failed to join handler thread");
+/*
+    request (pre-)votes from every peer, and check whether a quorum of the cluster config granted them. The requests
+    all go out before any reply is awaited, so an election on the driver takes one response timeout at most
+ */
+fn collect_votes(msg_type: MessageType, term: i32, leader_transfer: bool) -> bool {
+    let raft_log: TheLog = get_raft_log();
+    let mut request_vote_payload = generate_request_vote_payload(raft_log, term, get_node_addr());
+    request_vote_payload.leader_transfer = leader_transfer;
+
+    let requests: Vec<(SocketAddr, thread::JoinHandle<Option<RequestVoteReplyPayload>>)> = get_raft_peers().addresses
+        .into_iter()
+        .map(|peer| {
+            let vote_msg_type = msg_type.clone();
+            let payload = request_vote_payload.clone();
+            (peer, thread::spawn(move || send_request_vote(peer.to_string(), vote_msg_type, payload)))
+        })
+        .collect();
+
+    let mut votes = vec![get_node_addr()]; //candidates vote for themselves
+    let mut newer_term = None;
+    for (peer, handler) in requests {
+        if let Some(reply) = handler.join().expect("Failed to join handler thread") {
+            if reply.granted {
+                votes.push(peer);
+            }
+            if reply.term > term {
+                newer_term = newer_term.max(Some(reply.term));
+            }
+        }
+    }
+
+    //a peer in a newer term ends the election
+    if let (MessageType::REQUEST_VOTE, Some(newer_term)) = (&msg_type, newer_term) {
+        initialize_hard_state(newer_term);
+        return false;
     }
 
+    println!("{:?} votes for term {}: {:#?}", msg_type, term, votes);
+    get_cluster_config().has_quorum(&votes)
+}
+
+//send a REQUEST_VOTE (or PRE_VOTE) to a peer, returning the peer's reply
+fn send_request_vote(dest_addr: String, msg_type: MessageType, request_vote: RequestVotePayload) -> Option<RequestVoteReplyPayload> {
+    match TcpStream::connect(dest_addr) {
+        Ok(mut stream) => {
+            let msg = RequestVoteMessage {
+                src_addr: stream.local_addr().unwrap(),
+                src_id: calculate_hash(&stream.local_addr().unwrap()),
+                msg_type,
+                payload: request_vote,
+            };
+
+            let serialized_bytes = serialize_request_vote(msg);
+            stream.write_all(serialized_bytes.as_bytes()).unwrap();
+
+            let resp = read_resp(stream)?;
+            match serde_json::from_str::<RequestVoteReplyMessage>(&resp) {
+                Ok(reply) => Some(reply.payload),
+                Err(_) => None,
+            }
+        }
+        Err(e) => {
+            println!("Failed to Connect to Server: {:#?}", e);
+            None
+        }
+    }
 }
 
 //REQUEST_VOTE Request
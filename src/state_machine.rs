@@ -0,0 +1,699 @@
+//module for applying committed log entries to the key/value state machine
+
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::sync::Mutex;
+use std::collections::HashMap;
+use serde::{Serialize, Deserialize};
+use crate::kv_store::{set_key, get_key, delete_key};
+use crate::log::{get_raft_log, entry_at, LogCommand};
+use crate::storage::{with_log_storage, SnapshotMetadata};
+use crate::watch::{record_event, start_history_at, WatchEvent, EventKind};
+use crate::namespaces::{DEFAULT_NAMESPACE, NamespaceSnapshot, kv_data_key, get_namespaces, set_namespaces,
+                        namespace_exists, is_valid_namespace_name};
+use crate::mvcc::{KvData, ScanPage, VersionedValue, value_at, put_revision, scan_range, scan_prefix, compact};
+
+//highest log index known to be committed
+static COMMIT_INDEX: AtomicI32 = AtomicI32::new(0);
+
+//highest log index applied to the state machine
+static LAST_APPLIED: AtomicI32 = AtomicI32::new(0);
+
+static APPLY_LOCK: Mutex<()> = Mutex::new(());
+
+//how many recent apply responses are kept for the leader to hand back to clients
+const MAX_APPLIED_RESPONSES: i32 = 1000;
+
+//how many keys a scan returns when the client does not set a limit
+pub const DEFAULT_SCAN_LIMIT: usize = 100;
+
+//how often (in milliseconds) the applied prefix of the log is recorded as snapshotted
+pub const SNAPSHOT_INTERVAL: u64 = 60000;
+
+static APPLIED_RESPONSES: Mutex<Option<HashMap<i32, CommandResponse>>> = Mutex::new(None);
+
+//result of applying a command to the state machine
+#[derive(Serialize, Deserialize, Debug, Clone, Hash, PartialEq)]
+pub struct CommandResponse {
+    pub success: bool,
+    pub value: Option<String>,
+    pub reason: String,
+    //version of the key after a write, or as read
+    #[serde(default)]
+    pub version: Option<u64>,
+    //results of a range or prefix scan
+    #[serde(default)]
+    pub page: Option<ScanPage>,
+    //revision a read was served at; further reads at it see the same state
+    #[serde(default)]
+    pub revision: Option<u64>,
+}
+
+impl CommandResponse {
+    pub fn ok(value: Option<String>) -> CommandResponse {
+        CommandResponse {
+            success: true,
+            value,
+            reason: String::from("ok"),
+            version: None,
+            page: None,
+            revision: None,
+        }
+    }
+
+    pub fn read(versioned: Option<VersionedValue>, revision: u64) -> CommandResponse {
+        CommandResponse {
+            value: versioned.as_ref().map(|versioned| versioned.value.clone()),
+            version: versioned.map(|versioned| versioned.version),
+            revision: Some(revision),
+            ..CommandResponse::ok(None)
+        }
+    }
+
+    pub fn scan(page: ScanPage, revision: u64) -> CommandResponse {
+        CommandResponse {
+            page: Some(page),
+            revision: Some(revision),
+            ..CommandResponse::ok(None)
+        }
+    }
+
+    pub fn failed(reason: String) -> CommandResponse {
+        CommandResponse {
+            success: false,
+            value: None,
+            reason,
+            version: None,
+            page: None,
+            revision: None,
+        }
+    }
+}
+
+//a condition of a transaction, checked against the key's current state; version 0 means the key does not exist
+#[derive(Serialize, Deserialize, Debug, Clone, Hash, PartialEq)]
+pub enum Compare {
+    Value { key: String, value: String },
+    Version { key: String, version: u64 },
+    //the lock is held with this fencing token, so a writer whose lock expired can not write
+    LockToken { name: String, token: u64 },
+}
+
+//a write applied by a transaction branch
+#[derive(Serialize, Deserialize, Debug, Clone, Hash, PartialEq)]
+pub enum TxnOp {
+    Set { key: String, value: String },
+    Delete { key: String },
+}
+
+//multi-key transaction: when every compare holds the success ops are applied, otherwise the failure ops
+#[derive(Serialize, Deserialize, Debug, Clone, Hash, PartialEq, Default)]
+pub struct Txn {
+    pub compare: Vec<Compare>,
+    pub success: Vec<TxnOp>,
+    pub failure: Vec<TxnOp>,
+}
+
+/*
+    a key lease: keys attached to it are deleted when it is revoked. Expiry is never decided by a server's own
+    clock; the leader tracks the ttl and proposes a RevokeLease entry, so every replica deletes the same keys
+    at the same log index
+ */
+#[derive(Serialize, Deserialize, Debug, Clone, Hash, PartialEq)]
+pub struct KeyLease {
+    pub ttl: u64,
+    //(namespace, key) of every key attached to the lease
+    pub keys: Vec<(String, String)>,
+}
+
+//per-client session state, replicated by applying RegisterSession and SessionCommand entries
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct ClientSession {
+    pub last_sequence: u64,
+    pub last_response: Option<CommandResponse>,
+}
+
+pub fn get_commit_index() -> i32 {
+    COMMIT_INDEX.load(Ordering::SeqCst)
+}
+
+//the commit index never moves backwards
+pub fn set_commit_index(commit_index: i32) {
+    COMMIT_INDEX.fetch_max(commit_index, Ordering::SeqCst);
+}
+
+pub fn get_last_applied() -> i32 {
+    LAST_APPLIED.load(Ordering::SeqCst)
+}
+
+/*
+    followers learn the commit index from the leader; it is only safe to adopt when the follower's entry at
+    that index has the leader's term, since matching entries mean the logs are identical up to that point
+ */
+pub fn follow_leader_commit(leader_commit: i32, leader_commit_term: i32) {
+    match entry_at(&get_raft_log(), leader_commit) {
+        Some(entry) if entry.leader_term == leader_commit_term => {
+            set_commit_index(leader_commit);
+            apply_committed_entries();
+        }
+        _ => {}
+    }
+}
+
+//apply every committed entry that has not been applied yet, in log order
+pub fn apply_committed_entries() {
+    //entries must be applied exactly once, even when several threads catch up at the same time
+    let _apply_guard = APPLY_LOCK.lock().unwrap();
+    let raft_log = get_raft_log();
+    let commit_index = get_commit_index();
+    while get_last_applied() < commit_index {
+        let index = get_last_applied() + 1;
+        if let Some(entry) = entry_at(&raft_log, index) {
+            if let Some(command) = entry.command {
+                let response = apply_command(index, command);
+                record_applied_response(index, response);
+            }
+        }
+        LAST_APPLIED.store(index, Ordering::SeqCst);
+        set_key(String::from("applied_index"), index.to_string());
+    }
+}
+
+/*
+    the state machine in the kv store reflects every entry up to the applied index kept with it, so a restarted
+    server carries on from there instead of applying its log again; those entries are committed too
+ */
+pub fn restore_applied_index() {
+    let applied_index: i32 = get_key(String::from("applied_index")).parse().unwrap_or_default();
+    LAST_APPLIED.store(applied_index, Ordering::SeqCst);
+    set_commit_index(applied_index);
+    start_history_at(applied_index);
+}
+
+/*
+    the kv store already holds the state machine up to the applied index, so a snapshot only records the index
+    and term it covers; nothing is recorded when no entry was applied since the last one
+ */
+pub fn take_snapshot() {
+    let last_applied = get_last_applied();
+    with_log_storage(|storage| {
+        if last_applied <= storage.snapshot_metadata().last_included_index {
+            return
+        }
+        if let Some(last_included_term) = storage.term(last_applied) {
+            storage.save_snapshot_metadata(&SnapshotMetadata { last_included_index: last_applied, last_included_term });
+            println!("Snapshot taken at index {}", last_applied);
+        }
+    });
+}
+
+fn apply_command(index: i32, command: LogCommand) -> CommandResponse {
+    match command {
+        LogCommand::Set { .. } | LogCommand::Delete { .. } | LogCommand::CompareAndSwap { .. }
+        | LogCommand::SetIfAbsent { .. } | LogCommand::DeleteIfVersion { .. } | LogCommand::Txn(_) => {
+            apply_key_command(index, DEFAULT_NAMESPACE, command)
+        }
+        LogCommand::Namespaced { namespace, command } => {
+            if !namespace_exists(&namespace) {
+                return CommandResponse::failed(format!("unknown namespace {}", namespace));
+            }
+            apply_key_command(index, &namespace, *command)
+        }
+        LogCommand::CreateNamespace { namespace } => {
+            let mut namespaces = get_namespaces();
+            if !is_valid_namespace_name(&namespace) || !namespaces.insert(namespace.clone()) {
+                return CommandResponse::failed(format!("can not create namespace {}", namespace));
+            }
+            println!("Applying namespace creation {}", namespace);
+            set_namespaces(&namespaces);
+            CommandResponse::ok(None)
+        }
+        LogCommand::DropNamespace { namespace } => {
+            let mut namespaces = get_namespaces();
+            if namespace == DEFAULT_NAMESPACE || !namespaces.remove(&namespace) {
+                return CommandResponse::failed(format!("can not drop namespace {}", namespace));
+            }
+            println!("Applying namespace drop {}", namespace);
+            set_namespaces(&namespaces);
+            delete_key(kv_data_key(&namespace)).unwrap();
+            //keys of the dropped namespace are gone, so leases no longer hold them
+            let mut leases = get_key_leases();
+            for key_lease in leases.values_mut() {
+                key_lease.keys.retain(|(lease_namespace, _)| *lease_namespace != namespace);
+            }
+            set_key_leases(&leases);
+            CommandResponse::ok(None)
+        }
+        //a restored keyspace replaces the namespace's keys and history; watches are not notified
+        LogCommand::RestoreNamespace { namespace, kv_data } => {
+            let mut namespaces = get_namespaces();
+            if !is_valid_namespace_name(&namespace) {
+                return CommandResponse::failed(format!("can not restore namespace {}", namespace));
+            }
+            println!("Applying namespace restore {}: {} keys", namespace, kv_data.len());
+            namespaces.insert(namespace.clone());
+            set_namespaces(&namespaces);
+            set_kv_data(&namespace, &kv_data);
+            CommandResponse::ok(None)
+        }
+        LogCommand::Compact { revision } => {
+            if revision >= index as u64 {
+                return CommandResponse::failed(format!("revision {} is not below the compaction index {}", revision, index));
+            }
+            if revision > get_compacted_revision() {
+                println!("Applying compaction up to revision {}", revision);
+                for namespace in get_namespaces() {
+                    let mut kv_data = get_kv_data(&namespace);
+                    compact(&mut kv_data, revision);
+                    set_kv_data(&namespace, &kv_data);
+                }
+                set_key(String::from("kv_compacted_revision"), revision.to_string());
+            }
+            CommandResponse::ok(None)
+        }
+        //the id of a key lease is the log index of its grant entry
+        LogCommand::GrantLease { ttl } => {
+            println!("Applying lease grant {} with ttl {}ms", index, ttl);
+            let mut leases = get_key_leases();
+            leases.insert(index as u64, KeyLease { ttl, keys: Vec::new() });
+            set_key_leases(&leases);
+            CommandResponse::ok(Some(index.to_string()))
+        }
+        LogCommand::RevokeLease { id } => {
+            let mut leases = get_key_leases();
+            let key_lease = match leases.remove(&id) {
+                Some(key_lease) => key_lease,
+                None => return CommandResponse::failed(format!("unknown lease {}", id)),
+            };
+            set_key_leases(&leases);
+            println!("Applying lease revocation {}, deleting {} keys", id, key_lease.keys.len());
+            for (namespace, key) in key_lease.keys {
+                remove_key(index, &namespace, key);
+            }
+            //locks are held on a lease of their own, with the lease id as fencing token
+            let mut locks = get_locks();
+            locks.retain(|_, token| *token != id);
+            set_locks(&locks);
+            CommandResponse::ok(None)
+        }
+        /*
+            a lock is acquired on a new key lease, both identified by the acquiring entry's index; that index is the
+            fencing token, which grows with every acquisition. The lock is released when the lease is revoked,
+            whether by the holder or by the leader when the ttl runs out
+         */
+        LogCommand::AcquireLock { name, ttl } => {
+            let mut locks = get_locks();
+            if let Some(token) = locks.get(&name) {
+                return CommandResponse {
+                    version: Some(*token),
+                    ..CommandResponse::failed(format!("lock {} is held with token {}", name, token))
+                };
+            }
+            println!("Applying lock acquisition {} with token {}", name, index);
+            let mut leases = get_key_leases();
+            leases.insert(index as u64, KeyLease { ttl, keys: Vec::new() });
+            set_key_leases(&leases);
+            locks.insert(name, index as u64);
+            set_locks(&locks);
+            CommandResponse {
+                version: Some(index as u64),
+                ..CommandResponse::ok(Some(index.to_string()))
+            }
+        }
+        //membership changes take effect when appended, not when committed
+        LogCommand::ConfigChange(_) | LogCommand::NoOp => CommandResponse::ok(None),
+        //the client id of a session is the log index of its registration entry
+        LogCommand::RegisterSession => {
+            let mut sessions = get_client_sessions();
+            sessions.insert(index as u64, ClientSession::default());
+            set_client_sessions(&sessions);
+            CommandResponse::ok(Some(index.to_string()))
+        }
+        LogCommand::SessionCommand { client_id, sequence, command } => {
+            let mut sessions = get_client_sessions();
+            let response = apply_session_command(&mut sessions, client_id, sequence, || apply_command(index, *command));
+            set_client_sessions(&sessions);
+            response
+        }
+    }
+}
+
+//commands on the keys of one namespace
+fn apply_key_command(index: i32, namespace: &str, command: LogCommand) -> CommandResponse {
+    match command {
+        LogCommand::Set { key, value, lease: None } => {
+            println!("Applying SET {}/{}", namespace, key);
+            write_key(index, namespace, key, value)
+        }
+        LogCommand::Set { key, value, lease: Some(lease) } => {
+            println!("Applying SET {}/{} with lease {}", namespace, key, lease);
+            if !get_key_leases().contains_key(&lease) {
+                return CommandResponse::failed(format!("unknown lease {}", lease));
+            }
+            let response = write_key(index, namespace, key.clone(), value);
+            let mut leases = get_key_leases();
+            if let Some(key_lease) = leases.get_mut(&lease) {
+                key_lease.keys.push((namespace.to_string(), key));
+            }
+            set_key_leases(&leases);
+            response
+        }
+        LogCommand::Delete { key } => {
+            println!("Applying DELETE {}/{}", namespace, key);
+            remove_key(index, namespace, key)
+        }
+        LogCommand::CompareAndSwap { .. } | LogCommand::SetIfAbsent { .. } | LogCommand::DeleteIfVersion { .. } => {
+            apply_conditional_command(index, namespace, command)
+        }
+        LogCommand::Txn(txn) => apply_txn(index, namespace, txn),
+        _ => CommandResponse::failed(String::from("not a key command")),
+    }
+}
+
+//conditional writes are checked against the key's current value and version when applied, so every server agrees
+fn apply_conditional_command(index: i32, namespace: &str, command: LogCommand) -> CommandResponse {
+    let (key, current) = match &command {
+        LogCommand::CompareAndSwap { key, .. } | LogCommand::SetIfAbsent { key, .. }
+        | LogCommand::DeleteIfVersion { key, .. } => (key.clone(), current_value(namespace, key)),
+        _ => return CommandResponse::failed(String::from("not a conditional command")),
+    };
+    if !precondition_holds(&command, current.as_ref()) {
+        println!("Condition failed for {}", key);
+        return CommandResponse {
+            success: false,
+            value: current.as_ref().map(|current| current.value.clone()),
+            reason: String::from("condition failed"),
+            version: current.map(|current| current.version),
+            page: None,
+            revision: None,
+        };
+    }
+    match command {
+        LogCommand::CompareAndSwap { key, value, .. } | LogCommand::SetIfAbsent { key, value } => {
+            println!("Applying conditional SET {}/{}", namespace, key);
+            write_key(index, namespace, key, value)
+        }
+        _ => {
+            println!("Applying conditional DELETE {}/{}", namespace, key);
+            remove_key(index, namespace, key)
+        }
+    }
+}
+
+/*
+    a transaction is a single log entry applied while holding the apply lock, so readers never see part of it
+    and every server picks the same branch; all of its writes get the entry's index as their version
+ */
+fn apply_txn(index: i32, namespace: &str, txn: Txn) -> CommandResponse {
+    let succeeded = txn.compare.iter().all(|compare| match compare {
+        Compare::LockToken { name, token } => get_locks().get(name) == Some(token),
+        Compare::Value { key, .. } | Compare::Version { key, .. } => compare_holds(compare, current_value(namespace, key).as_ref()),
+    });
+    let ops = if succeeded { txn.success } else { txn.failure };
+    println!("Applying transaction, compare {}: {} ops", if succeeded { "succeeded" } else { "failed" }, ops.len());
+    for op in ops {
+        match op {
+            TxnOp::Set { key, value } => write_key(index, namespace, key, value),
+            TxnOp::Delete { key } => remove_key(index, namespace, key),
+        };
+    }
+    CommandResponse {
+        success: succeeded,
+        value: None,
+        reason: String::from(if succeeded { "ok" } else { "compare failed, failure ops applied" }),
+        version: Some(index as u64),
+        page: None,
+        revision: None,
+    }
+}
+
+pub fn compare_holds(compare: &Compare, current: Option<&VersionedValue>) -> bool {
+    match compare {
+        Compare::Value { value, .. } => current.is_some_and(|current| current.value == *value),
+        Compare::Version { version, .. } => current.map_or(0, |current| current.version) == *version,
+        //checked against the held locks when the transaction is applied
+        Compare::LockToken { .. } => false,
+    }
+}
+
+//whether the condition of a conditional write holds for the key's current value; other commands always apply
+pub fn precondition_holds(command: &LogCommand, current: Option<&VersionedValue>) -> bool {
+    match command {
+        LogCommand::CompareAndSwap { expected, .. } => current.is_some_and(|current| current.value == *expected),
+        LogCommand::SetIfAbsent { .. } => current.is_none(),
+        LogCommand::DeleteIfVersion { version, .. } => current.is_some_and(|current| current.version == *version),
+        _ => true,
+    }
+}
+
+//any write replaces a key's lease: a key that is set again without one no longer expires
+fn write_key(index: i32, namespace: &str, key: String, value: String) -> CommandResponse {
+    detach_key_lease(namespace, &key);
+    let mut kv_data = get_kv_data(namespace);
+    put_revision(&mut kv_data, key.clone(), index as u64, Some(value.clone()));
+    set_kv_data(namespace, &kv_data);
+    record_event(WatchEvent { index, namespace: namespace.to_string(), kind: EventKind::Put, key, value: Some(value) });
+    CommandResponse {
+        version: Some(index as u64),
+        ..CommandResponse::ok(None)
+    }
+}
+
+fn remove_key(index: i32, namespace: &str, key: String) -> CommandResponse {
+    detach_key_lease(namespace, &key);
+    let mut kv_data = get_kv_data(namespace);
+    if kv_data.get(&key).and_then(|revisions| value_at(revisions, None)).is_some() {
+        put_revision(&mut kv_data, key.clone(), index as u64, None);
+        set_kv_data(namespace, &kv_data);
+        record_event(WatchEvent { index, namespace: namespace.to_string(), kind: EventKind::Delete, key, value: None });
+    }
+    CommandResponse::ok(None)
+}
+
+fn detach_key_lease(namespace: &str, key: &str) {
+    let mut leases = get_key_leases();
+    let mut detached = false;
+    for key_lease in leases.values_mut() {
+        let attached = key_lease.keys.len();
+        key_lease.keys.retain(|(lease_namespace, lease_key)| lease_namespace != namespace || lease_key != key);
+        detached |= key_lease.keys.len() != attached;
+    }
+    if detached {
+        set_key_leases(&leases);
+    }
+}
+
+pub fn get_key_leases() -> HashMap<u64, KeyLease> {
+    serde_json::from_str(&get_key(String::from("key_leases"))).unwrap_or_default()
+}
+
+fn set_key_leases(leases: &HashMap<u64, KeyLease>) {
+    let serialized = serde_json::to_string(leases).unwrap();
+    set_key(String::from("key_leases"), serialized);
+}
+
+//held locks by name, with their fencing token
+pub fn get_locks() -> HashMap<String, u64> {
+    serde_json::from_str(&get_key(String::from("locks"))).unwrap_or_default()
+}
+
+fn set_locks(locks: &HashMap<String, u64>) {
+    let serialized = serde_json::to_string(locks).unwrap();
+    set_key(String::from("locks"), serialized);
+}
+
+fn current_value(namespace: &str, key: &str) -> Option<VersionedValue> {
+    get_kv_data(namespace).get(key).and_then(|revisions| value_at(revisions, None))
+}
+
+fn get_kv_data(namespace: &str) -> KvData {
+    serde_json::from_str(&get_key(kv_data_key(namespace))).unwrap_or_default()
+}
+
+fn set_kv_data(namespace: &str, kv_data: &KvData) {
+    let serialized = serde_json::to_string(kv_data).unwrap();
+    set_key(kv_data_key(namespace), serialized);
+}
+
+//oldest revision that can still be read
+fn get_compacted_revision() -> u64 {
+    get_key(String::from("kv_compacted_revision")).parse().unwrap_or_default()
+}
+
+//a read is served as of the requested revision, or the last applied one; history before compaction is gone
+fn read_revision(namespace: &str, revision: Option<u64>) -> Result<u64, String> {
+    if !namespace_exists(namespace) {
+        return Err(format!("unknown namespace {}", namespace));
+    }
+    let last_applied = get_last_applied() as u64;
+    match revision {
+        Some(revision) if revision < get_compacted_revision() => {
+            Err(format!("revision {} has been compacted, the oldest readable revision is {}", revision,
+                        get_compacted_revision()))
+        }
+        Some(revision) if revision > last_applied => Err(format!("revision {} has not been applied yet", revision)),
+        Some(revision) => Ok(revision),
+        None => Ok(last_applied),
+    }
+}
+
+//range scan of the state machine, never in the middle of applying an entry
+pub fn read_range(namespace: &str, start: &str, end: Option<&str>, limit: usize, revision: Option<u64>)
+                  -> Result<CommandResponse, String> {
+    let _apply_guard = APPLY_LOCK.lock().unwrap();
+    let revision = read_revision(namespace, revision)?;
+    Ok(CommandResponse::scan(scan_range(&get_kv_data(namespace), start, end, limit, Some(revision)), revision))
+}
+
+pub fn read_prefix(namespace: &str, prefix: &str, start: Option<&str>, limit: usize, revision: Option<u64>)
+                   -> Result<CommandResponse, String> {
+    let _apply_guard = APPLY_LOCK.lock().unwrap();
+    let revision = read_revision(namespace, revision)?;
+    Ok(CommandResponse::scan(scan_prefix(&get_kv_data(namespace), prefix, start, limit, Some(revision)), revision))
+}
+
+//copy of a namespace's whole keyspace, for per-namespace snapshots
+pub fn read_namespace_snapshot(namespace: &str) -> Result<CommandResponse, String> {
+    let _apply_guard = APPLY_LOCK.lock().unwrap();
+    let revision = read_revision(namespace, None)?;
+    let snapshot = NamespaceSnapshot { namespace: namespace.to_string(), revision, kv_data: get_kv_data(namespace) };
+    Ok(CommandResponse {
+        revision: Some(revision),
+        ..CommandResponse::ok(Some(serde_json::to_string(&snapshot).unwrap()))
+    })
+}
+
+pub fn read_namespaces() -> Result<CommandResponse, String> {
+    let namespaces: Vec<String> = get_namespaces().into_iter().collect();
+    Ok(CommandResponse::ok(Some(namespaces.join(","))))
+}
+
+/*
+    exactly-once semantics: a command tagged with (client_id, sequence) is only applied if the sequence is newer
+    than the session's last one; a retried duplicate gets the cached response instead of being applied again
+ */
+pub fn apply_session_command<F>(sessions: &mut HashMap<u64, ClientSession>, client_id: u64, sequence: u64,
+                                apply: F) -> CommandResponse where F: FnOnce() -> CommandResponse {
+    let session = match sessions.get_mut(&client_id) {
+        Some(session) => session,
+        None => return CommandResponse::failed(format!("unknown client session {}", client_id)),
+    };
+    if sequence <= session.last_sequence {
+        return match &session.last_response {
+            Some(response) if sequence == session.last_sequence => response.clone(),
+            _ => CommandResponse::failed(format!("sequence {} was already applied", sequence)),
+        };
+    }
+    let response = apply();
+    session.last_sequence = sequence;
+    session.last_response = Some(response.clone());
+    response
+}
+
+//cached response for a retried request, so the leader can answer without proposing it again
+pub fn cached_session_response(client_id: u64, sequence: u64) -> Option<CommandResponse> {
+    let sessions = get_client_sessions();
+    let session = sessions.get(&client_id)?;
+    if sequence == session.last_sequence {
+        session.last_response.clone()
+    } else {
+        None
+    }
+}
+
+fn get_client_sessions() -> HashMap<u64, ClientSession> {
+    serde_json::from_str(&get_key(String::from("client_sessions"))).unwrap_or_default()
+}
+
+fn set_client_sessions(sessions: &HashMap<u64, ClientSession>) {
+    let serialized = serde_json::to_string(sessions).unwrap();
+    set_key(String::from("client_sessions"), serialized);
+}
+
+fn record_applied_response(index: i32, response: CommandResponse) {
+    let mut applied_responses = APPLIED_RESPONSES.lock().unwrap();
+    let responses = applied_responses.get_or_insert_with(HashMap::new);
+    responses.insert(index, response);
+    responses.retain(|applied_index, _| *applied_index > index - MAX_APPLIED_RESPONSES);
+}
+
+//response of the command applied at a log index, for the leader to return to the proposing client
+pub fn take_applied_response(index: i32) -> Option<CommandResponse> {
+    APPLIED_RESPONSES.lock().unwrap().as_mut()?.remove(&index)
+}
+
+//read a key and its version from the state machine, never in the middle of applying an entry
+pub fn read_key(namespace: &str, key: &str, revision: Option<u64>) -> Result<CommandResponse, String> {
+    let _apply_guard = APPLY_LOCK.lock().unwrap();
+    let revision = read_revision(namespace, revision)?;
+    let versioned = get_kv_data(namespace).get(key).and_then(|revisions| value_at(revisions, Some(revision)));
+    Ok(CommandResponse::read(versioned, revision))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_duplicate_session_command_returns_cached_response() {
+        let mut sessions = HashMap::new();
+        sessions.insert(7, ClientSession::default());
+        let mut applied = 0;
+
+        let first = apply_session_command(&mut sessions, 7, 1, || { applied += 1; CommandResponse::ok(Some(String::from("a"))) });
+        let retry = apply_session_command(&mut sessions, 7, 1, || { applied += 1; CommandResponse::ok(Some(String::from("b"))) });
+
+        assert_eq!(applied, 1);
+        assert_eq!(first, retry);
+        assert_eq!(retry.value, Some(String::from("a")));
+    }
+
+    #[test]
+    fn test_session_sequences() {
+        let mut sessions = HashMap::new();
+        sessions.insert(7, ClientSession::default());
+
+        assert!(apply_session_command(&mut sessions, 7, 1, || CommandResponse::ok(None)).success);
+        assert!(apply_session_command(&mut sessions, 7, 2, || CommandResponse::ok(None)).success);
+        //an older sequence is not applied again, and its response is no longer cached
+        assert!(!apply_session_command(&mut sessions, 7, 1, || CommandResponse::ok(None)).success);
+        //commands for unregistered sessions are rejected
+        assert!(!apply_session_command(&mut sessions, 8, 1, || CommandResponse::ok(None)).success);
+    }
+
+    #[test]
+    fn test_conditional_write_preconditions() {
+        let current = VersionedValue { value: String::from("a"), version: 4 };
+        let cas = |expected: &str| LogCommand::CompareAndSwap {
+            key: String::from("k"),
+            expected: String::from(expected),
+            value: String::from("b"),
+        };
+        assert!(precondition_holds(&cas("a"), Some(&current)));
+        assert!(!precondition_holds(&cas("x"), Some(&current)));
+        assert!(!precondition_holds(&cas("a"), None));
+
+        let set_if_absent = LogCommand::SetIfAbsent { key: String::from("k"), value: String::from("b") };
+        assert!(precondition_holds(&set_if_absent, None));
+        assert!(!precondition_holds(&set_if_absent, Some(&current)));
+
+        let delete_if_version = |version| LogCommand::DeleteIfVersion { key: String::from("k"), version };
+        assert!(precondition_holds(&delete_if_version(4), Some(&current)));
+        assert!(!precondition_holds(&delete_if_version(3), Some(&current)));
+        assert!(!precondition_holds(&delete_if_version(4), None));
+    }
+
+    #[test]
+    fn test_txn_compares() {
+        let current = VersionedValue { value: String::from("a"), version: 4 };
+        let value = |value: &str| Compare::Value { key: String::from("k"), value: String::from(value) };
+        let version = |version| Compare::Version { key: String::from("k"), version };
+
+        assert!(compare_holds(&value("a"), Some(&current)));
+        assert!(!compare_holds(&value("b"), Some(&current)));
+        assert!(!compare_holds(&value("a"), None));
+        assert!(compare_holds(&version(4), Some(&current)));
+        assert!(!compare_holds(&version(0), Some(&current)));
+        //version 0 checks that the key does not exist
+        assert!(compare_holds(&version(0), None));
+    }
+}
@@ -0,0 +1,316 @@
+//module for log storage: where the Raft log and hard state are kept, behind the LogStorage trait
+
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Write};
+use std::ops::Range;
+use std::path::Path;
+use std::sync::Mutex;
+use serde::{Serialize, Deserialize};
+use crate::kv_store::{get_meta, set_meta, sync_to_disk, MetaKey};
+use crate::log::{LogEntry, HardState};
+
+//the Raft log of a server using the file-backed storage
+const LOG_FILE_PATH: &str = "raft_log.jsonl";
+
+//the storage the server's log and hard state go to; the file-backed one until set_log_storage is called
+static LOG_STORAGE: Mutex<Option<Box<dyn LogStorage + Send>>> = Mutex::new(None);
+
+/*
+    the log prefix covered by a snapshot: the index and term of the last entry it includes. It is recorded for
+    log compaction; entries are not dropped from storage when it is saved
+ */
+#[derive(Serialize, Deserialize, Debug, Clone, Hash, PartialEq, Default)]
+pub struct SnapshotMetadata {
+    pub last_included_index: i32,
+    pub last_included_term: i32,
+}
+
+/*
+    Raft state a server keeps across restarts. Log entries are indexed from 1; index 0 is the empty log before the
+    first entry, with term 0. Servers reply to peers right after a write, so a durable storage must have synced it
+    to disk by the time the call returns
+ */
+pub trait LogStorage {
+    //index of the last entry, 0 for an empty log
+    fn last_index(&self) -> i32;
+
+    //term of the entry at index, if the log has it or it is the last one covered by the snapshot
+    fn term(&self, index: i32) -> Option<i32>;
+
+    //entries with indexes in range, in log order; indexes past the end of the log are left out
+    fn entries(&self, range: Range<i32>) -> Vec<LogEntry>;
+
+    //add entries after the last one
+    fn append(&mut self, entries: &[LogEntry]);
+
+    //remove the entry at from_index and every entry after it
+    fn truncate_suffix(&mut self, from_index: i32);
+
+    fn hard_state(&self) -> HardState;
+
+    fn save_hard_state(&mut self, hard_state: &HardState);
+
+    fn snapshot_metadata(&self) -> SnapshotMetadata;
+
+    fn save_snapshot_metadata(&mut self, metadata: &SnapshotMetadata);
+}
+
+/*
+    storage on disk: the log goes to a file of its own, one JSON entry per line, and the hard state and snapshot
+    metadata to the Raft metadata file. Every write is synced. The log is parsed once, when the server starts, and
+    kept in memory; an append only writes the new entries, and a truncation cuts the file where the first removed
+    entry starts
+ */
+pub struct FileLogStorage {
+    file: File,
+    memory: MemoryLogStorage,
+    //byte offset in the file where each entry's line ends
+    entry_ends: Vec<u64>,
+}
+
+impl FileLogStorage {
+    pub fn open(path: &str) -> FileLogStorage {
+        let (file, log_entries, entry_ends) = open_log_file(path);
+        let memory = MemoryLogStorage {
+            log_entries,
+            hard_state: get_meta(MetaKey::HardState).unwrap_or_default(),
+            snapshot_metadata: get_meta(MetaKey::SnapshotMetadata).unwrap_or_default(),
+        };
+        FileLogStorage { file, memory, entry_ends }
+    }
+}
+
+/*
+    load the log file at path, creating it if needed, with where each entry ends. A crash during an append can
+    leave a partial last line; that entry was never acknowledged, so the line is cut off
+ */
+fn open_log_file(path: &str) -> (File, Vec<LogEntry>, Vec<u64>) {
+    let created = !Path::new(path).exists();
+    let mut file = OpenOptions::new().read(true).append(true).create(true).open(path).unwrap();
+    if created {
+        sync_to_disk(path).unwrap();
+    }
+
+    let mut contents = Vec::new();
+    file.read_to_end(&mut contents).unwrap();
+    let mut log_entries = Vec::new();
+    let mut entry_ends = Vec::new();
+    let mut line_start = 0;
+    for line in contents.split_inclusive(|byte| *byte == b'\n') {
+        match serde_json::from_slice::<LogEntry>(line) {
+            Ok(entry) if line.ends_with(b"\n") => {
+                line_start += line.len() as u64;
+                log_entries.push(entry);
+                entry_ends.push(line_start);
+            }
+            _ => break,
+        }
+    }
+    if line_start < contents.len() as u64 {
+        println!("Dropping a partly written entry at the end of {}", path);
+        file.set_len(line_start).unwrap();
+        file.sync_all().unwrap();
+    }
+    (file, log_entries, entry_ends)
+}
+
+impl LogStorage for FileLogStorage {
+    fn last_index(&self) -> i32 {
+        self.memory.last_index()
+    }
+
+    fn term(&self, index: i32) -> Option<i32> {
+        self.memory.term(index)
+    }
+
+    fn entries(&self, range: Range<i32>) -> Vec<LogEntry> {
+        self.memory.entries(range)
+    }
+
+    //the whole batch goes out in one write and one sync
+    fn append(&mut self, entries: &[LogEntry]) {
+        let mut end = self.entry_ends.last().copied().unwrap_or(0);
+        let mut lines = Vec::new();
+        for entry in entries {
+            let mut line = serde_json::to_vec(entry).unwrap();
+            line.push(b'\n');
+            end += line.len() as u64;
+            self.entry_ends.push(end);
+            lines.extend_from_slice(&line);
+        }
+        self.file.write_all(&lines).unwrap();
+        self.file.sync_data().unwrap();
+        self.memory.append(entries);
+    }
+
+    fn truncate_suffix(&mut self, from_index: i32) {
+        let kept = (from_index - 1).max(0) as usize;
+        if kept >= self.entry_ends.len() {
+            return;
+        }
+        let end = if kept == 0 { 0 } else { self.entry_ends[kept - 1] };
+        self.file.set_len(end).unwrap();
+        self.file.sync_data().unwrap();
+        self.entry_ends.truncate(kept);
+        self.memory.truncate_suffix(from_index);
+    }
+
+    fn hard_state(&self) -> HardState {
+        self.memory.hard_state()
+    }
+
+    fn save_hard_state(&mut self, hard_state: &HardState) {
+        set_meta(MetaKey::HardState, hard_state);
+        self.memory.save_hard_state(hard_state);
+    }
+
+    fn snapshot_metadata(&self) -> SnapshotMetadata {
+        self.memory.snapshot_metadata()
+    }
+
+    fn save_snapshot_metadata(&mut self, metadata: &SnapshotMetadata) {
+        set_meta(MetaKey::SnapshotMetadata, metadata);
+        self.memory.save_snapshot_metadata(metadata);
+    }
+}
+
+//storage that only lives as long as the process, for tests and throwaway servers
+#[derive(Debug, Clone, Default)]
+pub struct MemoryLogStorage {
+    log_entries: Vec<LogEntry>,
+    hard_state: HardState,
+    snapshot_metadata: SnapshotMetadata,
+}
+
+impl LogStorage for MemoryLogStorage {
+    fn last_index(&self) -> i32 {
+        self.log_entries.len() as i32
+    }
+
+    fn term(&self, index: i32) -> Option<i32> {
+        match index {
+            0 => Some(0),
+            _ if index == self.snapshot_metadata.last_included_index => Some(self.snapshot_metadata.last_included_term),
+            _ if index < 0 => None,
+            _ => self.log_entries.get((index - 1) as usize).map(|entry| entry.leader_term),
+        }
+    }
+
+    fn entries(&self, range: Range<i32>) -> Vec<LogEntry> {
+        let start = (range.start - 1).max(0) as usize;
+        let end = ((range.end - 1).max(0) as usize).min(self.log_entries.len());
+        self.log_entries.get(start..end).map_or(Vec::new(), |entries| entries.to_vec())
+    }
+
+    fn append(&mut self, entries: &[LogEntry]) {
+        self.log_entries.extend_from_slice(entries);
+    }
+
+    fn truncate_suffix(&mut self, from_index: i32) {
+        self.log_entries.truncate((from_index - 1).max(0) as usize);
+    }
+
+    fn hard_state(&self) -> HardState {
+        self.hard_state.clone()
+    }
+
+    fn save_hard_state(&mut self, hard_state: &HardState) {
+        self.hard_state = hard_state.clone();
+    }
+
+    fn snapshot_metadata(&self) -> SnapshotMetadata {
+        self.snapshot_metadata.clone()
+    }
+
+    fn save_snapshot_metadata(&mut self, metadata: &SnapshotMetadata) {
+        self.snapshot_metadata = metadata.clone();
+    }
+}
+
+//choose where the log and hard state are kept; called once at startup, before anything is stored
+pub fn set_log_storage(storage: Box<dyn LogStorage + Send>) {
+    *LOG_STORAGE.lock().unwrap() = Some(storage);
+}
+
+/*
+    run f with exclusive access to the server's log storage, so a read-modify-write of the log is never
+    interleaved with another thread's; f must not call back into the storage. The file-backed storage also goes
+    through the metadata file's own lock for the hard state, like every other metadata write, so none of them is lost
+ */
+pub fn with_log_storage<T, F>(f: F) -> T where F: FnOnce(&mut dyn LogStorage) -> T {
+    let mut storage = LOG_STORAGE.lock().unwrap();
+    f(storage.get_or_insert_with(|| Box::new(FileLogStorage::open(LOG_FILE_PATH))).as_mut())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(term: i32) -> LogEntry {
+        LogEntry {
+            leader_term: term,
+            leader_id: String::from("leader"),
+            prev_index: 0,
+            prev_term: 0,
+            leader_commit_index: 0,
+            command: None,
+        }
+    }
+
+    #[test]
+    fn test_memory_log_storage() {
+        let mut storage = MemoryLogStorage::default();
+        assert_eq!(storage.last_index(), 0);
+        assert_eq!(storage.term(0), Some(0));
+
+        storage.append(&[entry(1), entry(1), entry(2)]);
+        assert_eq!(storage.last_index(), 3);
+        assert_eq!(storage.term(3), Some(2));
+        assert_eq!(storage.term(4), None);
+        let terms: Vec<i32> = storage.entries(2..10).iter().map(|entry| entry.leader_term).collect();
+        assert_eq!(terms, vec![1, 2]);
+
+        storage.truncate_suffix(2);
+        assert_eq!(storage.last_index(), 1);
+
+        //the last index covered by a snapshot keeps its term
+        storage.save_snapshot_metadata(&SnapshotMetadata { last_included_index: 5, last_included_term: 4 });
+        assert_eq!(storage.term(5), Some(4));
+
+        storage.save_hard_state(&HardState { current_term: 3, voted_for: None });
+        assert_eq!(storage.hard_state().current_term, 3);
+    }
+    //a file log storage for tests; its hard state and snapshot metadata are never loaded from the metadata file
+    fn open_test_log(path: &str) -> FileLogStorage {
+        let (file, log_entries, entry_ends) = open_log_file(path);
+        FileLogStorage { file, memory: MemoryLogStorage { log_entries, ..Default::default() }, entry_ends }
+    }
+
+    fn terms(storage: &dyn LogStorage) -> Vec<i32> {
+        storage.entries(1..storage.last_index() + 1).iter().map(|entry| entry.leader_term).collect()
+    }
+
+    #[test]
+    fn test_file_log_storage() {
+        let path = std::env::temp_dir().join(format!("poncho-raft-test-{}.jsonl", std::process::id()));
+        let path = path.to_str().unwrap();
+        let _ = std::fs::remove_file(path);
+
+        let mut storage = open_test_log(path);
+        storage.append(&[entry(1), entry(1)]);
+        storage.append(&[entry(2), entry(2)]);
+        storage.truncate_suffix(3);
+        storage.append(&[entry(3)]);
+        assert_eq!(terms(&storage), vec![1, 1, 3]);
+        drop(storage);
+
+        //the log is loaded again as it was written, and a partly written entry at the end is dropped
+        let mut file = OpenOptions::new().append(true).open(path).unwrap();
+        file.write_all(b"{\"leader_term\":4,").unwrap();
+        let storage = open_test_log(path);
+        assert_eq!(terms(&storage), vec![1, 1, 3]);
+        assert_eq!(std::fs::metadata(path).unwrap().len(), *storage.entry_ends.last().unwrap());
+
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,257 @@
+//module for watches: streaming every applied change to a key or prefix to clients
+
+use std::collections::VecDeque;
+use std::io::{BufRead, BufReader, Write};
+use std::net::TcpStream;
+use std::sync::Mutex;
+use std::{thread, time};
+use serde::{Serialize, Deserialize};
+use crate::message::{calculate_hash, MessageType, WatchRequest, WatchRequestMessage, WatchResponse};
+use crate::state_machine::get_last_applied;
+use crate::namespaces::DEFAULT_NAMESPACE;
+
+//how many applied changes are kept for watches to resume from
+const MAX_WATCH_HISTORY: usize = 10000;
+
+//how often a watch stream polls for newly applied changes
+const WATCH_POLL_INTERVAL: u64 = 50;
+
+//a watch stream with no changes still sends the applied index this often, so clients can detect a dead server
+const WATCH_PROGRESS_INTERVAL: u64 = 2000;
+
+//how long the client waits between reconnection attempts
+const WATCH_RECONNECT_DELAY: u64 = 1000;
+
+static WATCH_HISTORY: Mutex<WatchHistory> = Mutex::new(WatchHistory { events: VecDeque::new(), trimmed_index: 0 });
+
+//recently applied changes; changes at or before trimmed_index have been dropped
+struct WatchHistory {
+    events: VecDeque<WatchEvent>,
+    trimmed_index: i32,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Hash, PartialEq)]
+pub enum EventKind {
+    Put,
+    Delete,
+}
+
+//a change applied to the state machine; a put's version is its index
+#[derive(Serialize, Deserialize, Debug, Clone, Hash, PartialEq)]
+pub struct WatchEvent {
+    pub index: i32,
+    pub namespace: String,
+    pub kind: EventKind,
+    pub key: String,
+    pub value: Option<String>,
+}
+
+//called by the state machine for every change it applies
+pub fn record_event(event: WatchEvent) {
+    record_in_history(&mut WATCH_HISTORY.lock().unwrap(), event);
+}
+
+/*
+    called at startup with the applied index restored from the kv store: the changes up to it were applied before
+    this server started and never recorded here, so a watch cannot resume from them
+ */
+pub fn start_history_at(applied_index: i32) {
+    start_history(&mut WATCH_HISTORY.lock().unwrap(), applied_index);
+}
+
+fn start_history(history: &mut WatchHistory, applied_index: i32) {
+    history.events.clear();
+    history.trimmed_index = applied_index;
+}
+
+fn record_in_history(history: &mut WatchHistory, event: WatchEvent) {
+    history.events.push_back(event);
+    while history.events.len() > MAX_WATCH_HISTORY {
+        if let Some(trimmed) = history.events.pop_front() {
+            history.trimmed_index = trimmed.index;
+        }
+    }
+}
+
+fn matches_watch(request: &WatchRequest, event: &WatchEvent) -> bool {
+    if event.namespace != request.namespace.as_deref().unwrap_or(DEFAULT_NAMESPACE) {
+        return false;
+    }
+    if request.prefix {
+        event.key.starts_with(&request.key)
+    } else {
+        event.key == request.key
+    }
+}
+
+/*
+    changes matching the watch at or after from_index, in log order; it is an error to resume from an index
+    whose changes are no longer kept, since the client would silently miss them
+ */
+fn events_since(history: &WatchHistory, request: &WatchRequest, from_index: i32) -> Result<Vec<WatchEvent>, String> {
+    if from_index <= history.trimmed_index {
+        return Err(format!("changes up to index {} are no longer available", history.trimmed_index));
+    }
+    Ok(history.events.iter()
+        .filter(|event| event.index >= from_index && matches_watch(request, event))
+        .cloned()
+        .collect())
+}
+
+/*
+    WATCH: the connection stays open and every applied change matching the request is streamed to the client
+    as one JSON WatchResponse per line, starting at the requested index; the stream ends when the client goes away
+ */
+pub fn stream_watch_events(mut socket: TcpStream, request: WatchRequest) {
+    let mut next_index = if request.start_index > 0 { request.start_index } else { get_last_applied() + 1 };
+    let mut last_sent = time::Instant::now();
+    loop {
+        let applied = get_last_applied();
+        let result = events_since(&WATCH_HISTORY.lock().unwrap(), &request, next_index);
+        let response = match result {
+            Ok(events) => {
+                if events.is_empty() && last_sent.elapsed() < time::Duration::from_millis(WATCH_PROGRESS_INTERVAL) {
+                    thread::sleep(time::Duration::from_millis(WATCH_POLL_INTERVAL));
+                    continue;
+                }
+                let last_event = events.last().map_or(0, |event| event.index);
+                next_index = next_index.max(applied + 1).max(last_event + 1);
+                WatchResponse { msg_type: MessageType::WATCH, events, applied, error: None }
+            }
+            Err(e) => WatchResponse { msg_type: MessageType::WATCH, events: Vec::new(), applied, error: Some(e) },
+        };
+
+        let mut serialized = serde_json::to_string(&response).unwrap();
+        serialized.push('\n');
+        if socket.write_all(serialized.as_bytes()).is_err() || response.error.is_some() {
+            println!("Watch on {} ended", request.key);
+            return;
+        }
+        last_sent = time::Instant::now();
+    }
+}
+
+/*
+    client side of a watch: prints every change, and when the server goes away (it crashed, or stopped sending
+    progress) reconnects to the next address, resuming after the last index it has seen
+ */
+pub fn watch_request(addresses: Vec<String>, namespace: Option<String>, key: String, prefix: bool, start_index: i32) {
+    let mut next_index = start_index;
+    for dest_addr in addresses.iter().cycle() {
+        println!("Watching {} on {} from index {}", key, dest_addr, next_index);
+        let request = WatchRequest { key: key.clone(), prefix, start_index: next_index, namespace: namespace.clone() };
+        match watch_stream(dest_addr, request, &mut next_index) {
+            WatchEnd::Closed => println!("Watch stream from {} closed", dest_addr),
+            WatchEnd::Failed(e) => println!("Watch stream from {} failed: {}", dest_addr, e),
+            WatchEnd::Rejected(e) => {
+                println!("Watch rejected by {}: {}", dest_addr, e);
+                return;
+            }
+        }
+        thread::sleep(time::Duration::from_millis(WATCH_RECONNECT_DELAY));
+    }
+}
+
+//how a watch stream from one server ended; only a rejected watch is not retried on another server
+enum WatchEnd {
+    Closed,
+    Failed(String),
+    Rejected(String),
+}
+
+fn watch_stream(dest_addr: &str, request: WatchRequest, next_index: &mut i32) -> WatchEnd {
+    let mut stream = match TcpStream::connect(dest_addr) {
+        Ok(stream) => stream,
+        Err(e) => return WatchEnd::Failed(e.to_string()),
+    };
+    let msg = WatchRequestMessage {
+        src_addr: stream.local_addr().unwrap(),
+        src_id: calculate_hash(&stream.local_addr().unwrap()),
+        msg_type: MessageType::WATCH,
+        payload: request,
+    };
+    let serialized_bytes = serde_json::to_string(&msg).unwrap();
+    if let Err(e) = stream.write_all(serialized_bytes.as_bytes()) {
+        return WatchEnd::Failed(e.to_string());
+    }
+
+    //a healthy server sends progress at least every WATCH_PROGRESS_INTERVAL
+    stream.set_read_timeout(Some(time::Duration::from_millis(WATCH_PROGRESS_INTERVAL * 3))).unwrap();
+    for line in BufReader::new(stream).lines() {
+        let response: WatchResponse = match line.map(|line| serde_json::from_str(&line)) {
+            Ok(Ok(response)) => response,
+            Ok(Err(e)) => return WatchEnd::Failed(e.to_string()),
+            Err(e) => return WatchEnd::Failed(e.to_string()),
+        };
+        if let Some(e) = response.error {
+            return WatchEnd::Rejected(e);
+        }
+        for event in response.events {
+            println!("{:?} {} = {:?} (index {})", event.kind, event.key, event.value, event.index);
+            *next_index = event.index + 1;
+        }
+        *next_index = (*next_index).max(response.applied + 1);
+    }
+    WatchEnd::Closed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(index: i32, key: &str) -> WatchEvent {
+        WatchEvent {
+            index,
+            namespace: String::from(DEFAULT_NAMESPACE),
+            kind: EventKind::Put,
+            key: String::from(key),
+            value: Some(String::from("v")),
+        }
+    }
+
+    fn watch(key: &str, prefix: bool) -> WatchRequest {
+        WatchRequest { key: String::from(key), prefix, start_index: 0, namespace: None }
+    }
+
+    fn history(events: Vec<WatchEvent>) -> WatchHistory {
+        let mut history = WatchHistory { events: VecDeque::new(), trimmed_index: 0 };
+        for event in events {
+            record_in_history(&mut history, event);
+        }
+        history
+    }
+
+    #[test]
+    fn test_events_since() {
+        let history = history(vec![event(2, "config/a"), event(3, "other"), event(5, "config/b"), event(6, "config")]);
+
+        let indexes = |events: Vec<WatchEvent>| events.iter().map(|event| event.index).collect::<Vec<_>>();
+        assert_eq!(indexes(events_since(&history, &watch("config/", true), 1).unwrap()), vec![2, 5]);
+        assert_eq!(indexes(events_since(&history, &watch("config/", true), 3).unwrap()), vec![5]);
+        assert_eq!(indexes(events_since(&history, &watch("config", false), 1).unwrap()), vec![6]);
+    }
+
+    #[test]
+    fn test_events_since_trimmed_history() {
+        let history = history((1..=MAX_WATCH_HISTORY as i32 + 2).map(|index| event(index, "k")).collect());
+
+        //resuming from a trimmed index would miss changes
+        assert!(events_since(&history, &watch("k", false), 2).is_err());
+        assert_eq!(events_since(&history, &watch("k", false), 3).unwrap().len(), MAX_WATCH_HISTORY);
+    }
+
+    #[test]
+    fn test_events_since_restart() {
+        let before_restart = history(vec![event(2, "k"), event(3, "k")]);
+        assert_eq!(events_since(&before_restart, &watch("k", false), 3).unwrap().len(), 1);
+
+        //the restarted server applied up to index 3 before it went down, and has recorded nothing since
+        let mut history = history(Vec::new());
+        start_history(&mut history, 3);
+        assert!(events_since(&history, &watch("k", false), 3).is_err());
+        assert!(events_since(&history, &watch("k", false), 4).unwrap().is_empty());
+
+        record_in_history(&mut history, event(4, "k"));
+        assert_eq!(events_since(&history, &watch("k", false), 4).unwrap(), vec![event(4, "k")]);
+    }
+}